@@ -0,0 +1,19 @@
+//! Parses arbitrary bytes as a DNS message, which has to end in
+//! either a packet or an error. Run with `cargo +nightly fuzz run
+//! from_buffer` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use signpost::packet::{BytePacketBuffer, DNSPacket};
+
+fuzz_target!(|data: &[u8]| {
+    // The same as `DNSPacket::from_bytes` without its length checks,
+    // the read path has to cope with whatever the buffer holds
+    let mut buffer = BytePacketBuffer::with_size(data.len());
+    buffer.buf.copy_from_slice(data);
+    buffer.set_len(data.len());
+    let _ = DNSPacket::from_buffer(&mut buffer);
+
+    let _ = DNSPacket::from_bytes(data);
+});
@@ -0,0 +1,133 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::name;
+
+/// Domains that shouldn't resolve, loaded from a hosts-file style list:
+///
+/// ```text
+/// # comment
+/// ads.example.com
+/// 0.0.0.0 tracker.example.net metrics.example.net
+/// ```
+///
+/// Lines may start with an address as in a hosts file, everything after
+/// it is treated as a domain. Listing a domain blocks its subdomains too,
+/// while `*.example.com` blocks only the subdomains.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+}
+
+impl Blocklist {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Blocklist> {
+        let text = fs::read_to_string(path)?;
+        Ok(Blocklist::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> Blocklist {
+        let mut domains = HashSet::new();
+
+        for line in text.lines() {
+            // Comments can also trail an entry
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            let mut fields = line.split_whitespace().peekable();
+            if fields
+                .peek()
+                .is_some_and(|field| field.parse::<IpAddr>().is_ok())
+            {
+                fields.next();
+            }
+
+            for name in fields {
+                let name = name::normalize(name);
+                // Hosts files map these to loopback, they aren't ads
+                if name.is_empty() || name == "localhost" || name == "localhost.localdomain" {
+                    continue;
+                }
+                domains.insert(name);
+            }
+        }
+
+        Blocklist { domains }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Whether the name, a wildcard covering it or any domain above it is listed
+    pub fn is_blocked(&self, qname: &str) -> bool {
+        if name::find_match(qname, |name| self.domains.contains(name)).is_some() {
+            return true;
+        }
+
+        // Unlike in zones, a listed domain covers everything below it
+        let qname = name::normalize(qname);
+        let mut name = qname.as_str();
+        while let Some((_, parent)) = name.split_once('.') {
+            if self.domains.contains(parent) {
+                return true;
+            }
+            name = parent;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "
+        # comment
+        ads.example.com
+        0.0.0.0 tracker.example.net metrics.example.net # trailing
+        *.wild.example.org
+        127.0.0.1 localhost
+    ";
+
+    #[test]
+    fn hosts_file_lines_are_parsed() {
+        let blocklist = Blocklist::parse(LIST);
+
+        assert_eq!(blocklist.len(), 4);
+        assert!(!blocklist.is_blocked("localhost"));
+    }
+
+    #[test]
+    fn listed_names_are_blocked() {
+        let blocklist = Blocklist::parse(LIST);
+
+        assert!(blocklist.is_blocked("ads.example.com"));
+        assert!(blocklist.is_blocked("Tracker.Example.NET."));
+        assert!(!blocklist.is_blocked("example.com"));
+        assert!(!blocklist.is_blocked("notads.example.com"));
+    }
+
+    #[test]
+    fn listed_parents_block_everything_below() {
+        let blocklist = Blocklist::parse(LIST);
+
+        assert!(blocklist.is_blocked("cdn.ads.example.com"));
+        assert!(blocklist.is_blocked("a.b.metrics.example.net"));
+    }
+
+    #[test]
+    fn wildcards_block_only_below() {
+        let blocklist = Blocklist::parse(LIST);
+
+        assert!(blocklist.is_blocked("a.wild.example.org"));
+        assert!(blocklist.is_blocked("a.b.wild.example.org"));
+        assert!(!blocklist.is_blocked("wild.example.org"));
+    }
+}
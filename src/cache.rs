@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::packet::{DNSRecord, QueryType, ResultCode};
+
+/// Times an entry has to be served before it's worth prefetching
+const PREFETCH_MIN_HITS: u32 = 3;
+
+/// Share of its TTL an entry has left when it gets prefetched, in percent
+const PREFETCH_THRESHOLD: u32 = 10;
+
+/// Entries a cache made with `new` holds
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// How often expired entries are cleared out, whether or
+/// not anyone asks for them again
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the cache knows about a question
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cached {
+    /// Records that are still alive
+    Answers(Vec<DNSRecord>),
+    /// The name or type doesn't exist, with the SOA that said so
+    Negative(ResultCode, Vec<DNSRecord>),
+}
+
+struct CacheEntry {
+    records: Vec<DNSRecord>,
+    /// Set for names or types that don't exist, `records`
+    /// then holds the SOA the answer came with
+    negative: Option<ResultCode>,
+    inserted: Instant,
+    /// When the last of the records runs out
+    expires: Instant,
+    /// Times the entry has been served
+    hits: u32,
+    /// Set once a refresh has been asked for, so only one goes out
+    prefetching: bool,
+}
+
+/// Name, type and whether DNSSEC records were asked for
+type Key = (String, QueryType, bool);
+
+struct Entries {
+    map: HashMap<Key, CacheEntry>,
+    max_entries: usize,
+    last_sweep: Instant,
+}
+
+impl Entries {
+    /// Drops the entries none of whose records are alive anymore
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        self.map.retain(|_, entry| entry.expires > now);
+        self.last_sweep = now;
+    }
+
+    /// Makes room for one more entry, first by sweeping and failing
+    /// that by dropping the entry closest to expiring
+    fn make_room(&mut self) {
+        if self.map.len() < self.max_entries {
+            return;
+        }
+
+        self.sweep();
+        if self.map.len() < self.max_entries {
+            return;
+        }
+
+        let soonest = self
+            .map
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = soonest {
+            self.map.remove(&key);
+        }
+    }
+}
+
+/// In-memory answers keyed by the question they answer.
+/// Records expire individually once their TTL runs out.
+///
+/// Answers to queries with the DO bit set carry RRSIGs the others
+/// don't, so the two are kept apart by the `dnssec_ok` flag.
+///
+/// The number of entries is capped, so a flood of queries for names
+/// that don't exist can't take up ever more memory. Once full, the
+/// entries closest to expiring make room for new ones.
+///
+/// Clones share the same entries, so a handle can be
+/// passed to every thread doing lookups.
+#[derive(Clone)]
+pub struct Cache {
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::with_capacity(DEFAULT_MAX_ENTRIES)
+    }
+
+    /// Cache holding at most `max_entries` answers, 0 caches nothing
+    pub fn with_capacity(max_entries: usize) -> Self {
+        Cache {
+            entries: Arc::new(Mutex::new(Entries {
+                map: HashMap::new(),
+                max_entries,
+                last_sweep: Instant::now(),
+            })),
+        }
+    }
+
+    /// Number of entries, expired ones that haven't been cleared out
+    /// yet included
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears out the entries that have expired. Storing does this
+    /// every once in a while on its own.
+    pub fn sweep(&self) {
+        self.entries.lock().unwrap().sweep();
+    }
+
+    /// Returns the answer or negative answer with the records that are
+    /// still alive, their TTLs decremented by the time spent in the cache
+    pub fn lookup(&self, qname: &str, qtype: QueryType, dnssec_ok: bool) -> Option<Cached> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (qname.to_lowercase(), qtype, dnssec_ok);
+
+        let entry = entries.map.get_mut(&key)?;
+        entry.hits = entry.hits.saturating_add(1);
+        let elapsed = entry.inserted.elapsed().as_secs().min(u32::MAX as u64) as u32;
+
+        let records: Vec<DNSRecord> = entry
+            .records
+            .iter()
+            .filter(|record| record.ttl() > elapsed)
+            .map(|record| record.with_ttl(record.ttl() - elapsed))
+            .collect();
+
+        if records.is_empty() {
+            entries.map.remove(&key);
+            return None;
+        }
+
+        Some(match entry.negative {
+            Some(rescode) => Cached::Negative(rescode, records),
+            None => Cached::Answers(records),
+        })
+    }
+
+    /// Whether an entry that keeps being asked for is about to run out,
+    /// so it should be refreshed before the next client has to wait for
+    /// that. True only once per entry, storing the refresh starts over.
+    pub fn should_prefetch(&self, qname: &str, qtype: QueryType, dnssec_ok: bool) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (qname.to_lowercase(), qtype, dnssec_ok);
+        let Some(entry) = entries.map.get_mut(&key) else {
+            return false;
+        };
+        if entry.negative.is_some() || entry.prefetching || entry.hits < PREFETCH_MIN_HITS {
+            return false;
+        }
+
+        let ttl = entry
+            .records
+            .iter()
+            .map(|record| record.ttl())
+            .min()
+            .unwrap_or(0);
+        let elapsed = entry.inserted.elapsed().as_secs().min(u32::MAX as u64) as u32;
+        let left = ttl.saturating_sub(elapsed);
+        if left as u64 * 100 >= ttl as u64 * PREFETCH_THRESHOLD as u64 {
+            return false;
+        }
+
+        entry.prefetching = true;
+        true
+    }
+
+    pub fn store(&self, qname: &str, qtype: QueryType, dnssec_ok: bool, records: &[DNSRecord]) {
+        if records.is_empty() {
+            return;
+        }
+
+        self.insert(
+            (qname.to_lowercase(), qtype, dnssec_ok),
+            records.to_vec(),
+            None,
+        );
+    }
+
+    /// Remembers that the name or type doesn't exist for as long as
+    /// RFC 2308 allows, the lower of the SOA's TTL and minimum field.
+    /// Without an SOA in `authorities` there's no telling how long
+    /// that is, so nothing is stored.
+    pub fn store_negative(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        dnssec_ok: bool,
+        rescode: ResultCode,
+        authorities: &[DNSRecord],
+    ) {
+        let Some(soa) = authorities.iter().find_map(|record| match record {
+            DNSRecord::SOA { minimum, .. } => Some(record.with_ttl(record.ttl().min(*minimum))),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        self.insert(
+            (qname.to_lowercase(), qtype, dnssec_ok),
+            vec![soa],
+            Some(rescode),
+        );
+    }
+
+    fn insert(&self, key: Key, records: Vec<DNSRecord>, negative: Option<ResultCode>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.max_entries == 0 {
+            return;
+        }
+        if entries.last_sweep.elapsed() >= SWEEP_INTERVAL {
+            entries.sweep();
+        }
+
+        if !entries.map.contains_key(&key) {
+            entries.make_room();
+        }
+
+        let inserted = Instant::now();
+        let ttl = records.iter().map(|record| record.ttl()).max().unwrap_or(0);
+        let entry = CacheEntry {
+            records,
+            negative,
+            inserted,
+            expires: inserted + Duration::from_secs(ttl as u64),
+            hits: 0,
+            prefetching: false,
+        };
+        entries.map.insert(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::QueryClass;
+    use std::thread;
+
+    fn a_record(addr: [u8; 4], ttl: u32) -> DNSRecord {
+        DNSRecord::A {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            addr: addr.into(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn stored_answers_are_served() {
+        let cache = Cache::new();
+        let records = [a_record([192, 0, 2, 1], 300)];
+        cache.store("example.com", QueryType::A, false, &records);
+
+        assert_eq!(
+            cache.lookup("EXAMPLE.com", QueryType::A, false),
+            Some(Cached::Answers(records.to_vec()))
+        );
+        assert_eq!(cache.lookup("example.com", QueryType::AAAA, false), None);
+    }
+
+    #[test]
+    fn ttls_count_down_until_records_expire() {
+        let cache = Cache::new();
+        let records = [a_record([192, 0, 2, 1], 1), a_record([192, 0, 2, 2], 60)];
+        cache.store("example.com", QueryType::A, false, &records);
+
+        thread::sleep(Duration::from_millis(1100));
+
+        assert_eq!(
+            cache.lookup("example.com", QueryType::A, false),
+            Some(Cached::Answers(vec![a_record([192, 0, 2, 2], 59)]))
+        );
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> DNSRecord {
+        DNSRecord::SOA {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            mname: "ns.example.com".into(),
+            rname: "admin.example.com".into(),
+            serial: 1,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum,
+            ttl,
+        }
+    }
+
+    #[test]
+    fn negative_answers_last_as_long_as_the_soa_allows() {
+        let cache = Cache::new();
+        let authorities = [soa(3600, 300)];
+        cache.store_negative(
+            "nope.example.com",
+            QueryType::A,
+            false,
+            ResultCode::NXDOMAIN,
+            &authorities,
+        );
+
+        assert_eq!(
+            cache.lookup("nope.example.com", QueryType::A, false),
+            Some(Cached::Negative(ResultCode::NXDOMAIN, vec![soa(300, 300)]))
+        );
+    }
+
+    #[test]
+    fn negative_answers_without_an_soa_are_not_stored() {
+        let cache = Cache::new();
+        cache.store_negative(
+            "nope.example.com",
+            QueryType::A,
+            false,
+            ResultCode::NXDOMAIN,
+            &[],
+        );
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn full_caches_drop_what_expires_first() {
+        let cache = Cache::with_capacity(2);
+        cache.store(
+            "a.example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 1], 60)],
+        );
+        cache.store(
+            "b.example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 2], 30)],
+        );
+        cache.store(
+            "c.example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 3], 90)],
+        );
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.lookup("b.example.com", QueryType::A, false).is_none());
+        assert!(cache.lookup("a.example.com", QueryType::A, false).is_some());
+        assert!(cache.lookup("c.example.com", QueryType::A, false).is_some());
+    }
+
+    #[test]
+    fn sweeping_clears_out_expired_entries() {
+        let cache = Cache::new();
+        cache.store(
+            "a.example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 1], 1)],
+        );
+        cache.store(
+            "b.example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 2], 60)],
+        );
+
+        thread::sleep(Duration::from_millis(1100));
+        cache.sweep();
+
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let cache = Cache::with_capacity(0);
+        cache.store(
+            "example.com",
+            QueryType::A,
+            false,
+            &[a_record([192, 0, 2, 1], 60)],
+        );
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn dnssec_answers_are_kept_apart() {
+        let cache = Cache::new();
+        let records = [a_record([192, 0, 2, 1], 300)];
+        cache.store("example.com", QueryType::A, false, &records);
+
+        assert_eq!(cache.lookup("example.com", QueryType::A, true), None);
+        assert!(cache.lookup("example.com", QueryType::A, false).is_some());
+    }
+}
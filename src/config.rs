@@ -0,0 +1,295 @@
+use std::env;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::cache::DEFAULT_MAX_ENTRIES;
+use crate::error::{DnsError, Result};
+
+/// Where answers that aren't in the zone or cache come from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ResolverMode {
+    /// Walk down from the root servers
+    #[default]
+    Recursive,
+    /// Hand queries to these resolvers, trying them in order
+    Forwarding(Vec<SocketAddr>),
+}
+
+/// Server settings, read from `SIGNPOST_*` environment variables
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `SIGNPOST_BIND`, e.g. 127.0.0.1:2053 keeps the
+    /// server reachable from localhost only, [::]:2053 listens on IPv6
+    pub bind: SocketAddr,
+    /// `SIGNPOST_ZONE`, zone file to serve authoritatively
+    /// before falling back to recursion
+    pub zone: Option<PathBuf>,
+    /// `SIGNPOST_DOH_URL`, resolve through a DNS-over-HTTPS endpoint
+    /// instead of walking down from the root servers
+    pub doh_url: Option<String>,
+    /// `SIGNPOST_FORWARDERS`, comma separated resolvers such as
+    /// 1.1.1.1,8.8.8.8:53 to forward to instead of recursing
+    pub mode: ResolverMode,
+    /// `SIGNPOST_ROOT_SERVERS`, comma separated servers to start
+    /// recursion at instead of the root servers, e.g. those of a
+    /// private namespace
+    pub root_servers: Option<Vec<SocketAddr>>,
+    /// `SIGNPOST_BLOCKLIST`, hosts-file style list of domains
+    /// to answer with NXDOMAIN instead of resolving
+    pub blocklist: Option<PathBuf>,
+    /// `SIGNPOST_PCAP`, file to capture client queries and responses
+    /// to, for opening in Wireshark
+    pub pcap: Option<PathBuf>,
+    /// `SIGNPOST_RANDOMIZE_CASE`, mix up the case of outgoing names and
+    /// reject responses that don't echo it. Off by default since some
+    /// servers normalize case.
+    pub randomize_case: bool,
+    /// `SIGNPOST_MINIMAL_RESPONSES`, leave the authority and additional
+    /// sections out of responses unless the answer can't do without them
+    pub minimal_responses: bool,
+    /// `SIGNPOST_SHUFFLE_ANSWERS`, send A and AAAA records in a different
+    /// order each time to spread clients over the addresses
+    pub shuffle_answers: bool,
+    /// `SIGNPOST_CACHE_SIZE`, answers the cache holds at most,
+    /// 0 turns caching off
+    pub cache_size: usize,
+    /// `SIGNPOST_PREFETCH`, refresh popular cache entries in the
+    /// background shortly before they expire
+    pub prefetch: bool,
+    /// `SIGNPOST_WORKERS`, threads handling queries. Recursion can take
+    /// seconds, each thread serves one client at a time.
+    pub workers: usize,
+    /// `SIGNPOST_MAX_UPSTREAM_QUERIES`, how many queries to other servers
+    /// a single client request may lead to before giving up with SERVFAIL
+    pub max_upstream_queries: usize,
+    /// `SIGNPOST_EDNS_SIZE`, largest UDP payload advertised upstream and to
+    /// clients. 1232 avoids IP fragmentation on virtually every path.
+    pub edns_payload_size: u16,
+    /// `SIGNPOST_RATE_LIMIT`, queries a second each client address may
+    /// send on average before being REFUSED, unlimited when unset
+    pub rate_limit: Option<u32>,
+    /// `SIGNPOST_RATE_BURST`, queries a client may send at once,
+    /// defaults to a second's worth of the rate limit
+    pub rate_burst: Option<u32>,
+    /// `SIGNPOST_RRL_LIMIT`, identical responses a second each client
+    /// netblock may get before response rate limiting kicks in
+    pub rrl_limit: Option<u32>,
+    /// `SIGNPOST_RRL_SLIP`, every how manyth limited response is sent
+    /// truncated instead of dropped, 0 drops them all
+    pub rrl_slip: u32,
+    /// `SIGNPOST_MIN_TTL`, raise shorter TTLs of resolved records to this
+    pub min_ttl: Option<u32>,
+    /// `SIGNPOST_MAX_TTL`, lower longer TTLs of resolved records to this
+    pub max_ttl: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: SocketAddr::from(([0, 0, 0, 0], 2053)),
+            zone: None,
+            doh_url: None,
+            mode: ResolverMode::Recursive,
+            root_servers: None,
+            blocklist: None,
+            pcap: None,
+            randomize_case: false,
+            minimal_responses: false,
+            shuffle_answers: false,
+            cache_size: DEFAULT_MAX_ENTRIES,
+            prefetch: false,
+            workers: 64,
+            max_upstream_queries: 50,
+            edns_payload_size: 1232,
+            rate_limit: None,
+            rate_burst: None,
+            rrl_limit: None,
+            rrl_slip: 2,
+            min_ttl: None,
+            max_ttl: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config> {
+        let mut config = Config::default();
+
+        if let Some(bind) = var("SIGNPOST_BIND") {
+            config.bind = parse("SIGNPOST_BIND", &bind)?;
+        }
+        config.zone = var("SIGNPOST_ZONE").map(PathBuf::from);
+        config.doh_url = var("SIGNPOST_DOH_URL");
+        if let Some(forwarders) = var("SIGNPOST_FORWARDERS") {
+            let forwarders = forwarders
+                .split(',')
+                .map(|addr| parse_server("SIGNPOST_FORWARDERS", addr.trim()))
+                .collect::<Result<_>>()?;
+            config.mode = ResolverMode::Forwarding(forwarders);
+        }
+        if config.doh_url.is_some() && config.mode != ResolverMode::Recursive {
+            return Err(DnsError::Config(
+                "SIGNPOST_DOH_URL and SIGNPOST_FORWARDERS can't both be set".into(),
+            ));
+        }
+        if let Some(roots) = var("SIGNPOST_ROOT_SERVERS") {
+            let roots = roots
+                .split(',')
+                .map(|addr| parse_server("SIGNPOST_ROOT_SERVERS", addr.trim()))
+                .collect::<Result<_>>()?;
+            config.root_servers = Some(roots);
+        }
+        config.blocklist = var("SIGNPOST_BLOCKLIST").map(PathBuf::from);
+        config.pcap = var("SIGNPOST_PCAP").map(PathBuf::from);
+        if let Some(randomize_case) = var("SIGNPOST_RANDOMIZE_CASE") {
+            config.randomize_case = parse("SIGNPOST_RANDOMIZE_CASE", &randomize_case)?;
+        }
+        if let Some(minimal) = var("SIGNPOST_MINIMAL_RESPONSES") {
+            config.minimal_responses = parse("SIGNPOST_MINIMAL_RESPONSES", &minimal)?;
+        }
+        if let Some(shuffle) = var("SIGNPOST_SHUFFLE_ANSWERS") {
+            config.shuffle_answers = parse("SIGNPOST_SHUFFLE_ANSWERS", &shuffle)?;
+        }
+        if let Some(size) = var("SIGNPOST_CACHE_SIZE") {
+            config.cache_size = parse("SIGNPOST_CACHE_SIZE", &size)?;
+        }
+        if let Some(prefetch) = var("SIGNPOST_PREFETCH") {
+            config.prefetch = parse("SIGNPOST_PREFETCH", &prefetch)?;
+        }
+
+        if let Some(workers) = var("SIGNPOST_WORKERS") {
+            config.workers = parse("SIGNPOST_WORKERS", &workers)?;
+            if config.workers == 0 {
+                return Err(DnsError::Config(
+                    "SIGNPOST_WORKERS must be at least 1".into(),
+                ));
+            }
+        }
+
+        if let Some(max) = var("SIGNPOST_MAX_UPSTREAM_QUERIES") {
+            config.max_upstream_queries = parse("SIGNPOST_MAX_UPSTREAM_QUERIES", &max)?;
+        }
+
+        if let Some(size) = var("SIGNPOST_EDNS_SIZE") {
+            config.edns_payload_size = parse("SIGNPOST_EDNS_SIZE", &size)?;
+            if config.edns_payload_size < 512 {
+                return Err(DnsError::Config(format!(
+                    "SIGNPOST_EDNS_SIZE {} is below the 512 bytes every server must take",
+                    size
+                )));
+            }
+        }
+
+        if let Some(rate) = var("SIGNPOST_RATE_LIMIT") {
+            config.rate_limit = Some(parse("SIGNPOST_RATE_LIMIT", &rate)?);
+        }
+        if let Some(burst) = var("SIGNPOST_RATE_BURST") {
+            config.rate_burst = Some(parse("SIGNPOST_RATE_BURST", &burst)?);
+        }
+
+        if let Some(limit) = var("SIGNPOST_RRL_LIMIT") {
+            config.rrl_limit = Some(parse("SIGNPOST_RRL_LIMIT", &limit)?);
+        }
+        if let Some(slip) = var("SIGNPOST_RRL_SLIP") {
+            config.rrl_slip = parse("SIGNPOST_RRL_SLIP", &slip)?;
+        }
+
+        if let Some(min) = var("SIGNPOST_MIN_TTL") {
+            config.min_ttl = Some(parse("SIGNPOST_MIN_TTL", &min)?);
+        }
+        if let Some(max) = var("SIGNPOST_MAX_TTL") {
+            config.max_ttl = Some(parse("SIGNPOST_MAX_TTL", &max)?);
+        }
+        if let (Some(min), Some(max)) = (config.min_ttl, config.max_ttl) {
+            if min > max {
+                return Err(DnsError::Config(format!(
+                    "SIGNPOST_MIN_TTL {} is above SIGNPOST_MAX_TTL {}",
+                    min, max
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Keeps a TTL within the configured bounds. A TTL of 0 asks
+    /// for the record not to be cached at all and is left alone.
+    pub fn clamp_ttl(&self, ttl: u32) -> u32 {
+        if ttl == 0 {
+            return 0;
+        }
+
+        let ttl = self.min_ttl.map_or(ttl, |min| ttl.max(min));
+        self.max_ttl.map_or(ttl, |max| ttl.min(max))
+    }
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Address of a DNS server, port 53 unless one is given
+fn parse_server(name: &str, value: &str) -> Result<SocketAddr> {
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => parse(name, value),
+    }
+}
+
+fn parse<T>(name: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| DnsError::Config(format!("{} {}: {}", name, value, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clamping(min_ttl: Option<u32>, max_ttl: Option<u32>) -> Config {
+        Config {
+            min_ttl,
+            max_ttl,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn ttls_are_kept_without_bounds() {
+        let config = Config::default();
+
+        assert_eq!(config.clamp_ttl(1), 1);
+        assert_eq!(config.clamp_ttl(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn ttls_are_raised_to_the_minimum() {
+        let config = clamping(Some(60), None);
+
+        assert_eq!(config.clamp_ttl(5), 60);
+        assert_eq!(config.clamp_ttl(60), 60);
+        assert_eq!(config.clamp_ttl(3600), 3600);
+    }
+
+    #[test]
+    fn ttls_are_lowered_to_the_maximum() {
+        let config = clamping(None, Some(3600));
+
+        assert_eq!(config.clamp_ttl(5), 5);
+        assert_eq!(config.clamp_ttl(3600), 3600);
+        assert_eq!(config.clamp_ttl(86400), 3600);
+    }
+
+    #[test]
+    fn zero_ttls_are_left_alone() {
+        let config = clamping(Some(60), Some(3600));
+
+        assert_eq!(config.clamp_ttl(0), 0);
+    }
+}
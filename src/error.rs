@@ -0,0 +1,155 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use crate::packet::ResultCode;
+
+pub type Result<T> = std::result::Result<T, DnsError>;
+
+#[derive(Debug)]
+pub enum DnsError {
+    /// Read or write past the end of the packet buffer
+    EndOfBuffer,
+    /// Too many compression pointers followed while reading a name,
+    /// `offset` is where reading gave up
+    JumpLimitExceeded {
+        offset: usize,
+        jumps: usize,
+    },
+    /// Compression pointer that doesn't point back to earlier data
+    InvalidPointer {
+        offset: usize,
+        target: usize,
+    },
+    /// Label longer than the 63 bytes allowed
+    LabelTooLong,
+    /// Name with nothing between two dots, or a leading dot
+    EmptyLabel,
+    /// Encoded name longer than the 255 bytes allowed
+    NameTooLong,
+    /// Host name with characters other than letters, digits and hyphens
+    InvalidHostname(String),
+    /// Packet or record data doesn't add up
+    Malformed(String),
+    /// Response id doesn't belong to the query that was sent
+    IdMismatch {
+        expected: u16,
+        got: u16,
+    },
+    /// Response answers a different question than the one asked
+    QuestionMismatch {
+        expected: String,
+        got: String,
+    },
+    /// Response doesn't echo the client cookie sent with the query
+    CookieMismatch,
+    /// Server didn't answer in time, worth retrying elsewhere
+    Timeout,
+    /// Every candidate name server failed
+    NoNameservers,
+    /// Resolution finished without a record for the name
+    NoAnswer(String),
+    /// Server answered with an error such as SERVFAIL or REFUSED
+    ErrorResponse {
+        server: SocketAddr,
+        rescode: ResultCode,
+    },
+    /// Resolving name servers led to too many nested lookups
+    RecursionLimit,
+    /// A single request took too many queries to other servers
+    QueryLimit,
+    /// Zone file couldn't be loaded
+    Zone(String),
+    /// Invalid setting supplied by the operator
+    Config(String),
+    /// Binding the server's address needs privileges the process lacks
+    BindDenied(SocketAddr),
+    /// DNS-over-HTTPS request failed or returned something unexpected
+    Http(String),
+    Io(io::Error),
+}
+
+impl DnsError {
+    /// Whether asking another server might work out
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DnsError::Timeout | DnsError::Io(_) | DnsError::Http(_)
+        )
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::EndOfBuffer => write!(f, "End of buffer"),
+            DnsError::JumpLimitExceeded { offset, jumps } => write!(
+                f,
+                "Exceeded name compression jump limit after {} jumps at offset {}",
+                jumps, offset
+            ),
+            DnsError::InvalidPointer { offset, target } => write!(
+                f,
+                "Compression pointer at offset {} points forward to {}",
+                offset, target
+            ),
+            DnsError::LabelTooLong => write!(f, "Label exceeds 63 characters"),
+            DnsError::EmptyLabel => write!(f, "Name contains an empty label"),
+            DnsError::NameTooLong => write!(f, "Name exceeds 255 bytes"),
+            DnsError::InvalidHostname(name) => write!(f, "Invalid host name {}", name),
+            DnsError::Malformed(msg) => write!(f, "Malformed packet: {}", msg),
+            DnsError::IdMismatch { expected, got } => {
+                write!(f, "Response id {} doesn't match query id {}", got, expected)
+            }
+            DnsError::QuestionMismatch { expected, got } => {
+                write!(
+                    f,
+                    "Response question {} doesn't match query {}",
+                    got, expected
+                )
+            }
+            DnsError::CookieMismatch => {
+                write!(f, "Response cookie doesn't match the one sent")
+            }
+            DnsError::Timeout => write!(f, "Timed out waiting for a response"),
+            DnsError::NoNameservers => write!(f, "No nameservers to query"),
+            DnsError::NoAnswer(name) => write!(f, "No answer for {}", name),
+            DnsError::ErrorResponse { server, rescode } => {
+                write!(f, "{} answered {}", server, rescode)
+            }
+            DnsError::RecursionLimit => write!(f, "Too many nested lookups"),
+            DnsError::QueryLimit => write!(f, "Too many upstream queries for one request"),
+            DnsError::Zone(msg) => write!(f, "Invalid zone: {}", msg),
+            DnsError::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            DnsError::BindDenied(addr) => write!(
+                f,
+                "Permission denied binding {}. Ports below 1024 need root or \
+                 `setcap cap_net_bind_service=+ep` on the binary, or set \
+                 SIGNPOST_BIND to a higher port.",
+                addr
+            ),
+            DnsError::Http(msg) => write!(f, "DNS-over-HTTPS failed: {}", msg),
+            DnsError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DnsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        // Blocking reads report an expired timeout as either of these
+        // depending on the platform
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DnsError::Timeout,
+            _ => DnsError::Io(e),
+        }
+    }
+}
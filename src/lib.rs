@@ -0,0 +1,16 @@
+pub mod blocklist;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod name;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod packet;
+pub mod pcap;
+pub mod ratelimit;
+pub mod resolver;
+pub mod roots;
+pub mod stats;
+pub mod stub;
+pub mod upstream;
+pub mod zone;
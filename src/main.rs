@@ -1,131 +1,693 @@
-use packet::{BytePacketBuffer, DNSPacket, Result};
-use std::net::{Ipv4Addr, UdpSocket};
+use log::{debug, error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signpost::blocklist::Blocklist;
+use signpost::cache::Cache;
+use signpost::config::{Config, ResolverMode};
+use signpost::error::{DnsError, Result};
+use signpost::name;
+use signpost::packet::{
+    BytePacketBuffer, DNSPacket, DNSRecord, OpCode, QueryClass, QueryType, ResultCode,
+};
+use signpost::pcap::PcapWriter;
+use signpost::ratelimit::{RateLimiter, ResponseRateLimiter, RrlAction};
+use signpost::resolver::{self, recursive_lookup, LOOKUP_TIMEOUT};
+use signpost::stats::Stats;
+use signpost::stub::{lookup, QueryFlags};
+use signpost::zone::Zone;
+use std::env;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use crate::packet::{DNSQuestion, QueryType, ResultCode};
+/// How often the server loop checks whether it should shut down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-mod packet;
+/// Queries waiting for a worker, any more are dropped until one is free
+const QUEUE_LENGTH: usize = 1024;
 
-fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DNSPacket> {
-    // a.root-servers.net
-    let mut ns: Ipv4Addr = "198.41.0.4".parse().unwrap();
+/// How long a TCP client may take to send its next query before
+/// the connection is closed
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
 
-    loop {
-        println!("Looking up {:?} {} with ns {}", qtype, qname, ns);
-
-        let ns_copy = ns;
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server)?;
+/// Query received and waiting for a worker to handle it
+enum Job {
+    /// Datagram from the UDP socket
+    Udp {
+        req_buffer: BytePacketBuffer,
+        source: SocketAddr,
+    },
+    /// Connection accepted on the TCP listener, which can carry
+    /// any number of queries
+    Tcp(TcpStream),
+}
 
-        // Answer and no errors -> we're done
-        if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
-            return Ok(response);
-        }
+/// How a query reached the server
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
 
-        // Doesn't exist
-        if response.header.rescode == ResultCode::NXDOMAIN {
-            return Ok(response);
-        }
+/// Everything handling a query needs to share with the rest of the
+/// server. Clones are cheap and refer to the same state, so a context
+/// can be moved into each thread handling queries.
+#[derive(Clone)]
+struct Context {
+    resolver: resolver::Context,
+    config: Arc<Config>,
+    zone: Option<Arc<Zone>>,
+    blocklist: Option<Arc<Blocklist>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    response_limiter: Option<Arc<ResponseRateLimiter>>,
+    pcap: Option<Arc<PcapWriter>>,
+    stats: Arc<Stats>,
+}
 
-        // Look somewhere else
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
-            continue;
-        }
+impl Context {
+    fn new(config: Config, zone: Option<Arc<Zone>>, blocklist: Option<Arc<Blocklist>>) -> Context {
+        let rate_limiter = config.rate_limit.map(|rate| {
+            let burst = config.rate_burst.unwrap_or(rate);
+            Arc::new(RateLimiter::new(rate, burst))
+        });
+        let response_limiter = config
+            .rrl_limit
+            .map(|limit| Arc::new(ResponseRateLimiter::new(limit, config.rrl_slip)));
 
-        // Resolve NS record to IP
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(name) => name,
-            None => return Ok(response),
-        };
+        let config = Arc::new(config);
+        let stats = Arc::new(Stats::new());
+        let cache = Cache::with_capacity(config.cache_size);
 
-        let recursive_resp = recursive_lookup(&new_ns_name, QueryType::A)?;
+        Context {
+            resolver: resolver::Context::new(config.clone(), cache, stats.clone()),
+            config,
+            zone,
+            blocklist,
+            rate_limiter,
+            response_limiter,
+            pcap: None,
+            stats,
+        }
+    }
 
-        if let Some(new_ns) = recursive_resp.get_random_a() {
-            ns = new_ns;
-        } else {
-            return Ok(response);
+    /// Copy for handling a single client request, with a budget of its own
+    fn for_request(&self) -> Context {
+        Context {
+            resolver: self.resolver.for_request(),
+            ..self.clone()
         }
     }
 }
 
-fn lookup(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16)) -> Result<DNSPacket> {
-    let socket = UdpSocket::bind(("0.0.0.0", 42069))?;
+/// Answers a query received over UDP, truncated to what the client
+/// can take
+fn handle_udp(
+    socket: &UdpSocket,
+    mut req_buffer: BytePacketBuffer,
+    source: SocketAddr,
+    ctx: &Context,
+) -> Result<()> {
+    // Captured before parsing, malformed queries are the interesting ones
+    capture(socket, source, &req_buffer, ctx, true);
 
-    let mut packet = DNSPacket::new();
-
-    packet.header.id = 1337;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet
-        .questions
-        .push(DNSQuestion::new(qname.to_string(), qtype));
-
-    let mut buffer = BytePacketBuffer::new();
-    packet.write(&mut buffer)?;
+    let mut request = DNSPacket::from_buffer(&mut req_buffer)?;
+    let Some(mut response) = handle_query(&mut request, source, Transport::Udp, ctx) else {
+        return Ok(());
+    };
 
-    socket.send_to(&buffer.buf[0..buffer.pos], server)?;
+    // Responses are only truncated past what both sides can take
+    let our_size = ctx.config.edns_payload_size as usize;
+    let payload_size = request.max_payload_size().min(our_size);
+    let mut resp_buffer = BytePacketBuffer::with_size(payload_size);
+    response.write_truncated(&mut resp_buffer)?;
 
-    let mut resp_buffer = BytePacketBuffer::new();
-    socket.recv(&mut resp_buffer.buf)?;
+    socket.send_to(resp_buffer.message(), source)?;
+    capture(socket, source, &resp_buffer, ctx, false);
 
-    DNSPacket::from_buffer(&mut resp_buffer)
+    Ok(())
 }
 
-fn handle_query(socket: &UdpSocket) -> Result<()> {
-    let mut req_buffer = BytePacketBuffer::new();
+/// Answers the queries sent over a TCP connection until the client
+/// closes it or goes quiet for `TCP_IDLE_TIMEOUT`. These aren't
+/// captured, the capture only holds UDP datagrams.
+fn handle_tcp(mut stream: TcpStream, ctx: &Context) -> Result<()> {
+    let source = stream.peer_addr()?;
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT))?;
 
-    let (_, source) = socket.recv_from(&mut req_buffer.buf)?;
+    loop {
+        let mut request = match DNSPacket::read_tcp(&mut stream) {
+            Ok(request) => request,
+            // Closed or idle, either way the client is done
+            Err(DnsError::Io(_) | DnsError::Timeout) => return Ok(()),
+            Err(e) => return Err(e),
+        };
 
-    let mut request = DNSPacket::from_buffer(&mut req_buffer)?;
+        // Each query gets a budget of its own, like one sent over UDP
+        let ctx = ctx.for_request();
+        if let Some(mut response) = handle_query(&mut request, source, Transport::Tcp, &ctx) {
+            response.write_tcp(&mut stream)?;
+        }
+    }
+}
+
+/// Works out the response to a query, or `None` when there shouldn't be one
+fn handle_query(
+    request: &mut DNSPacket,
+    source: SocketAddr,
+    transport: Transport,
+    ctx: &Context,
+) -> Option<DNSPacket> {
+    // Answering a response could bounce packets back and forth
+    // forever, so misrouted or spoofed ones are dropped
+    if !request.is_query() {
+        warn!("Dropping response sent by {}", source);
+        return None;
+    }
 
     let mut response = DNSPacket::new();
     response.header.id = request.header.id;
+    response.header.opcode = request.header.opcode;
     response.header.recursion_desired = true;
     response.header.recursion_available = true;
+    response.header.checking_disabled = request.header.checking_disabled;
     response.header.response = true;
 
-    if let Some(question) = request.questions.pop() {
-        println!("Received query: {:?}", question);
-        if let Ok(result) = recursive_lookup(&question.name, question.qtype) {
-            response.questions.push(question);
-            response.header.rescode = result.header.rescode;
+    let resolver = ctx.resolver.with_flags(QueryFlags {
+        checking_disabled: request.header.checking_disabled,
+        dnssec_ok: request.dnssec_ok(),
+    });
+    // A forwarder's AA and AD bits are passed on, it's the one
+    // that looked at the data
+    let forwarding = matches!(ctx.config.mode, ResolverMode::Forwarding(_));
+
+    let limited = ctx
+        .rate_limiter
+        .as_ref()
+        .is_some_and(|limiter| !limiter.allow(source.ip()));
+
+    if limited {
+        // A refusal is no bigger than the query, so it
+        // can't be used for amplification
+        debug!("Rate limiting {}", source);
+        response.header.rescode = ResultCode::REFUSED;
+        response.questions = std::mem::take(&mut request.questions);
+    } else if request.header.opcode != OpCode::QUERY {
+        // Only standard queries are implemented, a NOTIFY or
+        // UPDATE shouldn't be mistaken for one
+        response.header.rescode = ResultCode::NOTIMP;
+        response.questions = std::mem::take(&mut request.questions);
+    } else if request.questions.is_empty() {
+        response.header.rescode = ResultCode::FORMERR;
+    }
+
+    // Only authoritative when every question was answered from the zone
+    // or by authoritative forwarders, and authenticated when every
+    // answer was
+    let mut authoritative = !request.questions.is_empty();
+    let mut authed_data = forwarding && !request.questions.is_empty();
+
+    for question in request.questions.drain(..) {
+        info!("Received query: {:?}", question);
+
+        let blocked = ctx
+            .blocklist
+            .as_ref()
+            .is_some_and(|blocklist| blocklist.is_blocked(&question.name));
+
+        let rescode = if question.class != QueryClass::IN {
+            // Recursion and zones only cover the internet class
+            authoritative = false;
+            authed_data = false;
+            ResultCode::NOTIMP
+        } else if blocked {
+            info!("Blocked {}", question.name);
+            authoritative = false;
+            authed_data = false;
+            ResultCode::NXDOMAIN
+        } else if let Some(result) = ctx
+            .zone
+            .as_ref()
+            .and_then(|zone| zone.lookup(&question.name, question.qtype))
+        {
+            // The zone isn't signed
+            authed_data = false;
+            response.answers.extend(result.answers);
+            response.authorities.extend(result.authorities);
+            result.header.rescode
+        } else if let Ok(result) = recursive_lookup(&question.name, question.qtype, &resolver) {
+            authoritative &= forwarding && result.header.authoritative_answer;
+            authed_data &= result.header.authed_data;
+            // Other servers' names are passed on as they answered them
+            response.raw_names = true;
+
+            let clamp = |rec: DNSRecord| rec.with_ttl(ctx.config.clamp_ttl(rec.ttl()));
 
             for rec in result.answers {
-                println!("Answer: {:?}", rec);
-                response.answers.push(rec);
+                debug!("Answer: {:?}", rec);
+                response.answers.push(clamp(rec));
             }
             for rec in result.authorities {
-                println!("Authority: {:?}", rec);
-                response.authorities.push(rec);
+                debug!("Authority: {:?}", rec);
+                response.authorities.push(clamp(rec));
             }
             for rec in result.resources {
-                println!("Resource: {:?}", rec);
-                response.resources.push(rec);
+                // OPT only applies to a single hop
+                if let DNSRecord::OPT { .. } = rec {
+                    continue;
+                }
+                debug!("Resource: {:?}", rec);
+                response.resources.push(clamp(rec));
             }
+            result.header.rescode
         } else {
-            response.header.rescode = ResultCode::SERVFAIL;
+            authoritative = false;
+            authed_data = false;
+            ResultCode::SERVFAIL
+        };
+
+        // Report the first question that went wrong
+        if response.header.rescode == ResultCode::NOERROR {
+            response.header.rescode = rescode;
         }
-    } else {
-        response.header.rescode = ResultCode::FORMERR;
+        response.questions.push(question);
+    }
+    response.header.authoritative_answer = authoritative;
+    response.header.authed_data = authed_data;
+
+    if ctx.config.shuffle_answers {
+        response.shuffle_addresses();
+    }
+
+    if ctx.config.minimal_responses {
+        // Without answers the authorities say why, e.g. the SOA
+        // negative caching goes by
+        if !response.answers.is_empty() {
+            response.authorities.clear();
+        }
+        response.resources.clear();
+    }
+    ctx.stats.record_response(response.header.rescode);
+
+    // A spoofed source can't complete the TCP handshake, so there's
+    // nothing to amplify and slipped responses have somewhere to retry
+    let response_limiter = ctx
+        .response_limiter
+        .as_ref()
+        .filter(|_| transport == Transport::Udp);
+    if let Some(limiter) = response_limiter {
+        match limiter.check(source.ip(), &response) {
+            RrlAction::Send => {}
+            RrlAction::Slip => {
+                debug!("Slipping response to {}", source);
+                response.answers.clear();
+                response.authorities.clear();
+                response.resources.clear();
+                response.header.truncated_message = true;
+            }
+            RrlAction::Drop => {
+                debug!("Dropping response to {}", source);
+                return None;
+            }
+        }
+    }
+
+    // Clients using EDNS0 expect it in the response as well
+    let our_size = ctx.config.edns_payload_size;
+    if request
+        .resources
+        .iter()
+        .any(|rec| matches!(rec, DNSRecord::OPT { .. }))
+    {
+        response
+            .resources
+            .push(DNSRecord::opt(our_size).with_dnssec_ok(request.dnssec_ok()));
+    }
+
+    Some(response)
+}
+
+/// Adds a message exchanged with `client` to the capture, if there is
+/// one. Failing to is only logged, the query is served regardless.
+fn capture(
+    socket: &UdpSocket,
+    client: SocketAddr,
+    buffer: &BytePacketBuffer,
+    ctx: &Context,
+    from_client: bool,
+) {
+    let Some(pcap) = &ctx.pcap else {
+        return;
+    };
+
+    let result = socket
+        .local_addr()
+        .map_err(DnsError::from)
+        .and_then(|local| {
+            if from_client {
+                pcap.record(client, local, buffer.message())
+            } else {
+                pcap.record(local, client, buffer.message())
+            }
+        });
+    if let Err(e) = result {
+        warn!("Failed to capture message: {}", e);
+    }
+}
+
+/// How to invoke the binary, shown when the arguments don't make sense
+const USAGE: &str =
+    "usage: signpost [serve] | signpost query <name> [type] [@server] | signpost check";
+
+/// Name looked up by `check`, the root servers' own names won't go away
+const SELF_TEST_NAME: &str = "a.root-servers.net";
+
+fn main() {
+    // Verbosity is controlled through RUST_LOG, e.g. RUST_LOG=debug
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    // Shown as a message rather than the debug form main would print
+    if let Err(e) = run() {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let config = Config::from_env()?;
+
+    #[cfg(not(feature = "doh"))]
+    if config.doh_url.is_some() {
+        return Err(DnsError::Config(
+            "SIGNPOST_DOH_URL requires building with the doh feature".into(),
+        ));
+    }
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None | Some("serve") => serve(config),
+        Some("query") => query(&args[1..], config),
+        Some("check") => self_test(&Context::new(config, None, None)),
+        Some(command) => Err(DnsError::Config(format!(
+            "Unknown command {}, {}",
+            command, USAGE
+        ))),
     }
+}
+
+/// Runs the server on UDP and TCP until SIGINT or SIGTERM, then
+/// waits for the queries being handled to be answered
+fn serve(config: Config) -> Result<()> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, shutdown.clone())?;
+    }
+
+    let bind_error = |e: io::Error| match e.kind() {
+        io::ErrorKind::PermissionDenied => DnsError::BindDenied(config.bind),
+        _ => e.into(),
+    };
+    let socket = UdpSocket::bind(config.bind).map_err(bind_error)?;
+    // Wake up now and then to notice a shutdown
+    socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+    let socket = Arc::new(socket);
+    // Clients retry over TCP when a response comes back truncated
+    let listener = TcpListener::bind(config.bind).map_err(bind_error)?;
 
-    let mut resp_buffer = BytePacketBuffer::new();
-    response.write(&mut resp_buffer)?;
-    let len = resp_buffer.pos;
-    let data = resp_buffer.peek_many(0, len)?;
+    let zone = match &config.zone {
+        Some(path) => Some(Arc::new(Zone::load(path)?)),
+        None => None,
+    };
 
-    socket.send_to(data, source)?;
+    let blocklist = match &config.blocklist {
+        Some(path) => {
+            let blocklist = Blocklist::load(path)?;
+            info!("Loaded {} blocked domains", blocklist.len());
+            Some(Arc::new(blocklist))
+        }
+        None => None,
+    };
+
+    let pcap = match &config.pcap {
+        Some(path) => {
+            info!("Capturing queries to {}", path.display());
+            Some(Arc::new(PcapWriter::create(path)?))
+        }
+        None => None,
+    };
+
+    let ctx = Context {
+        pcap,
+        ..Context::new(config, zone, blocklist)
+    };
+
+    // Recursion can take seconds, other clients shouldn't wait on it.
+    // A fixed number of workers keeps a flood of queries from spawning
+    // threads without end, what they can't keep up with is dropped.
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LENGTH);
+    let workers = spawn_workers(ctx.config.workers, rx, &socket, &ctx, &shutdown);
+
+    // Blocks accepting connections for as long as the process runs,
+    // nothing it hands over after a shutdown gets picked up anymore
+    let tcp_tx = tx.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(TrySendError::Full(_)) = tcp_tx.try_send(Job::Tcp(stream)) {
+                        debug!("Closing connection, every worker is busy");
+                    }
+                }
+                Err(e) => error!("An error occurred: {}", e),
+            }
+        }
+    });
 
+    while !shutdown.load(Ordering::Relaxed) {
+        let mut req_buffer = BytePacketBuffer::new();
+        let source = match socket.recv_from(&mut req_buffer.buf) {
+            Ok((len, source)) => {
+                req_buffer.set_len(len);
+                source
+            }
+            // Timed out or cut short by a signal, check for shutdown
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => {
+                error!("An error occurred: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(TrySendError::Full(_)) = tx.try_send(Job::Udp { req_buffer, source }) {
+            debug!("Dropping query from {}, every worker is busy", source);
+        }
+    }
+
+    // Workers finish the queries already queued, then stop
+    info!("Shutting down, waiting on queries in flight");
+    for handle in workers {
+        let _ = handle.join();
+    }
+
+    let stats = ctx.stats.snapshot();
+    info!(
+        "Handled {} queries, {} cache hits, {} SERVFAIL, {} NXDOMAIN",
+        stats.queries, stats.cache_hits, stats.servfails, stats.nxdomains
+    );
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let socket = UdpSocket::bind(("0.0.0.0", 2053))?;
+/// Starts the threads handling queries, each taking the next one off
+/// the queue until it's empty after a shutdown. UDP responses go out
+/// through the shared socket to each query's source.
+fn spawn_workers(
+    count: usize,
+    rx: Receiver<Job>,
+    socket: &Arc<UdpSocket>,
+    ctx: &Context,
+    shutdown: &Arc<AtomicBool>,
+) -> Vec<thread::JoinHandle<()>> {
+    let rx = Arc::new(Mutex::new(rx));
 
-    loop {
-        match handle_query(&socket) {
-            Ok(_) => {}
-            Err(e) => eprintln!("An error occurred: {}", e),
+    (0..count)
+        .map(|_| {
+            let rx = rx.clone();
+            let socket = socket.clone();
+            let ctx = ctx.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || loop {
+                // Only held while waiting, not while handling the query
+                let job = {
+                    let rx = rx.lock().unwrap();
+                    // Once shutting down only what's already queued is left
+                    if shutdown.load(Ordering::Relaxed) {
+                        rx.try_recv().map_err(|_| RecvTimeoutError::Disconnected)
+                    } else {
+                        rx.recv_timeout(SHUTDOWN_POLL_INTERVAL)
+                    }
+                };
+                let result = match job {
+                    Ok(Job::Udp { req_buffer, source }) => {
+                        handle_udp(&socket, req_buffer, source, &ctx.for_request())
+                    }
+                    Ok(Job::Tcp(stream)) => handle_tcp(stream, &ctx),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if let Err(e) = result {
+                    error!("An error occurred: {}", e);
+                }
+            })
+        })
+        .collect()
+}
+
+/// Checks that names can be resolved at all, for supervisors and
+/// container health checks. The process exits with an error if not.
+fn self_test(ctx: &Context) -> Result<()> {
+    let response = recursive_lookup(SELF_TEST_NAME, QueryType::A, &ctx.resolver)?;
+
+    match response.get_random_a() {
+        Some(addr) => {
+            info!("Resolved {} to {}", SELF_TEST_NAME, addr);
+            Ok(())
         }
+        None => Err(DnsError::NoAnswer(format!(
+            "{} ({})",
+            SELF_TEST_NAME,
+            response.header.rescode.to_str()
+        ))),
+    }
+}
+
+/// Does a single lookup and prints the response, asking `@server`
+/// directly when given and resolving from the roots otherwise
+fn query(args: &[String], config: Config) -> Result<()> {
+    let mut qname = None;
+    let mut qtype = QueryType::A;
+    let mut server = None;
+
+    for arg in args {
+        if let Some(addr) = arg.strip_prefix('@') {
+            // Port 53 unless one is given
+            let addr = addr
+                .parse::<SocketAddr>()
+                .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| DnsError::Config(format!("Invalid server address {}", addr)))?;
+            server = Some(addr);
+        } else if qname.is_none() {
+            qname = Some(arg.as_str());
+        } else {
+            qtype = arg.parse()?;
+        }
+    }
+
+    // Asked for in A-labels so the response matches the question
+    let qname = qname.ok_or_else(|| DnsError::Config(USAGE.into()))?;
+    let qname = &name::to_ascii(qname);
+
+    let response = match server {
+        Some(server) => lookup(
+            qname,
+            qtype,
+            server,
+            LOOKUP_TIMEOUT,
+            config.randomize_case,
+            config.edns_payload_size,
+            QueryFlags::default(),
+        )?,
+        None => recursive_lookup(qname, qtype, &Context::new(config, None, None).resolver)?,
+    };
+
+    print!("{:#}", response);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_dropped_unanswered() {
+        // Nothing listens on the discard port, a lookup would only time out
+        let config = Config {
+            mode: ResolverMode::Forwarding(vec![SocketAddr::from(([127, 0, 0, 1], 9))]),
+            ..Config::default()
+        };
+        let ctx = Context::new(config, None, None);
+
+        let mut request = DNSPacket::query("example.com", QueryType::A);
+        request.header.response = true;
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+
+        assert!(handle_query(&mut request, source, Transport::Udp, &ctx).is_none());
+
+        let stats = ctx.stats.snapshot();
+        assert_eq!(stats.hops, 0);
+        assert_eq!(stats.queries, 0);
+    }
+
+    #[test]
+    fn bursts_from_one_source_are_refused() {
+        let config = Config {
+            rate_limit: Some(1),
+            rate_burst: Some(3),
+            ..Config::default()
+        };
+        let ctx = Context::new(config, None, None);
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+        let other = SocketAddr::from(([192, 0, 2, 2], 5353));
+
+        // Without a question there's nothing to look up
+        let rescode = |source| {
+            let mut request = DNSPacket::new();
+            handle_query(&mut request, source, Transport::Udp, &ctx)
+                .unwrap()
+                .header
+                .rescode
+        };
+
+        for _ in 0..3 {
+            assert_eq!(rescode(source), ResultCode::FORMERR);
+        }
+        assert_eq!(rescode(source), ResultCode::REFUSED);
+        assert_eq!(rescode(other), ResultCode::FORMERR);
+    }
+
+    #[test]
+    fn identical_udp_responses_are_slipped() {
+        let config = Config {
+            rrl_limit: Some(1),
+            rrl_slip: 1,
+            ..Config::default()
+        };
+        let ctx = Context::new(config, None, None);
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+
+        // Answered NOTIMP without looking anything up
+        let respond = |transport| {
+            let mut request = DNSPacket::query("example.com", QueryType::A);
+            request.header.opcode = OpCode::NOTIFY;
+            handle_query(&mut request, source, transport, &ctx).unwrap()
+        };
+
+        assert!(!respond(Transport::Udp).header.truncated_message);
+        assert!(respond(Transport::Udp).header.truncated_message);
+        // A TCP client can't have spoofed its address
+        assert!(!respond(Transport::Tcp).header.truncated_message);
     }
 }
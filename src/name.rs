@@ -0,0 +1,471 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Form names are compared in: lowercase without the trailing dot
+pub fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Whether `name` is `domain` itself or somewhere below it
+pub fn is_subdomain(name: &str, domain: &str) -> bool {
+    let name = normalize(name);
+    let domain = normalize(domain);
+
+    domain.is_empty() || name == domain || name.ends_with(&format!(".{}", domain))
+}
+
+/// Name a DNAME from `owner` to `target` redirects `name` to, with the
+/// owner suffix swapped for the target (RFC 6672). Only names below the
+/// owner are redirected, and none whose new name would be too long.
+pub fn substitute_dname(name: &str, owner: &str, target: &str) -> Option<String> {
+    let name = name.trim_end_matches('.');
+    let owner = normalize(owner);
+    let target = target.trim_end_matches('.');
+
+    let prefix = if owner.is_empty() {
+        name
+    } else {
+        let split = name.len().checked_sub(owner.len() + 1)?;
+        let (prefix, suffix) = name.split_at(split);
+        if !suffix.eq_ignore_ascii_case(&format!(".{}", owner)) {
+            return None;
+        }
+        prefix
+    };
+    if prefix.is_empty() {
+        return None;
+    }
+
+    let substituted = if target.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{}.{}", prefix, target)
+    };
+    (encoded_len(&substituted) <= 255).then_some(substituted)
+}
+
+/// Bytes the name takes on the wire without compression. Each label
+/// takes a length byte, which is where the dots are, plus one more at
+/// the start and the root at the end.
+pub fn encoded_len(name: &str) -> usize {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        1
+    } else {
+        name.len() + 2
+    }
+}
+
+/// Whether every label follows the letters, digits and hyphen rule of
+/// RFC 952 and RFC 1123 for host names, not starting or ending with a
+/// hyphen. A leading `*` label is let through for wildcard owners.
+pub fn is_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        return true;
+    }
+    let name = name.strip_prefix("*.").unwrap_or(name);
+
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    })
+}
+
+/// Finds the entry that answers `qname` following the wildcard rules
+/// of RFC 1034 4.3.3. An exact match wins, otherwise each ancestor is
+/// tried for a `*.<ancestor>` entry, stopping at the first ancestor
+/// that exists since wildcards don't reach past existing names.
+///
+/// `contains` tells whether an entry exists for a normalized name.
+/// Returns the key of the matching entry, so `a.b.example.com` against
+/// a `*.example.com` entry gives `*.example.com`. The wildcard doesn't
+/// match `example.com` itself.
+pub fn find_match<F>(qname: &str, contains: F) -> Option<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let qname = normalize(qname);
+    if contains(&qname) {
+        return Some(qname);
+    }
+
+    let mut name = qname.as_str();
+    while let Some((_, parent)) = name.split_once('.') {
+        let wildcard = format!("*.{}", parent);
+        if contains(&wildcard) {
+            return Some(wildcard);
+        }
+        if contains(parent) {
+            return None;
+        }
+        name = parent;
+    }
+
+    None
+}
+
+/// Flips the case of each letter at random, the 0x20 trick from
+/// draft-vixie-dnsext-dns0x20. Servers echo the question as sent, so a
+/// spoofed response would have to guess the case on top of the id.
+pub fn randomize_case(name: &str) -> String {
+    let mut bits = 0;
+    let mut bits_left = 0;
+
+    name.chars()
+        .map(|c| {
+            if !c.is_ascii_alphabetic() {
+                return c;
+            }
+            if bits_left == 0 {
+                bits = RandomState::new().build_hasher().finish();
+                bits_left = 64;
+            }
+            let upper = bits & 1 == 1;
+            bits >>= 1;
+            bits_left -= 1;
+
+            if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+/// Name with its Unicode labels turned into `xn--` A-labels, which is
+/// all the wire format can carry. Labels are lowercased before being
+/// encoded but otherwise taken as they are, there's no full IDNA
+/// mapping. Labels that can't be encoded are left alone.
+pub fn to_ascii(name: &str) -> Cow<'_, str> {
+    if name.is_ascii() {
+        return Cow::Borrowed(name);
+    }
+
+    let labels: Vec<String> = name
+        .split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                return label.to_string();
+            }
+            match punycode_encode(&label.to_lowercase()) {
+                Some(encoded) => format!("{}{}", ACE_PREFIX, encoded),
+                None => label.to_string(),
+            }
+        })
+        .collect();
+    Cow::Owned(labels.join("."))
+}
+
+/// Name with its `xn--` labels decoded for display, undoing `to_ascii`.
+/// Labels that don't decode are left alone.
+pub fn to_unicode(name: &str) -> Cow<'_, str> {
+    let is_ace = |label: &str| {
+        label
+            .get(..ACE_PREFIX.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ACE_PREFIX))
+    };
+    if !name.split('.').any(is_ace) {
+        return Cow::Borrowed(name);
+    }
+
+    let labels: Vec<String> = name
+        .split('.')
+        .map(|label| {
+            if !is_ace(label) {
+                return label.to_string();
+            }
+            punycode_decode(&label[ACE_PREFIX.len()..]).unwrap_or_else(|| label.to_string())
+        })
+        .collect();
+    Cow::Owned(labels.join("."))
+}
+
+/// Marks a label as punycode-encoded
+const ACE_PREFIX: &str = "xn--";
+
+// Parameters of RFC 3492 6.1 for punycode
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Encodes a label as in RFC 3492 6.3, without the `xn--` prefix
+fn punycode_encode(label: &str) -> Option<String> {
+    let input: Vec<u32> = label.chars().map(|c| c as u32).collect();
+
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+
+    while (handled as usize) < input.len() {
+        let m = *input.iter().filter(|&&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+
+        for &c in &input {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+
+    Some(output)
+}
+
+/// Decodes a label as in RFC 3492 6.2, given without the `xn--` prefix
+fn punycode_decode(label: &str) -> Option<String> {
+    let (basic, extended) = match label.rfind('-') {
+        Some(i) => (&label[..i], &label[i + 1..]),
+        None => ("", label),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes();
+
+    while digits.len() > 0 {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(digit: u32) -> char {
+    if digit < 26 {
+        (b'a' + digit as u8) as char
+    } else {
+        (b'0' + (digit - 26) as u8) as char
+    }
+}
+
+fn decode_digit(b: u8) -> Option<u32> {
+    match b {
+        b'a'..=b'z' => Some((b - b'a') as u32),
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'0'..=b'9' => Some((b - b'0') as u32 + 26),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dname_swaps_the_owner_suffix() {
+        assert_eq!(
+            substitute_dname("www.example.com", "example.com", "example.net").as_deref(),
+            Some("www.example.net")
+        );
+        assert_eq!(
+            substitute_dname("a.b.Example.COM.", "example.com.", "other.org.").as_deref(),
+            Some("a.b.other.org")
+        );
+    }
+
+    #[test]
+    fn dname_only_redirects_names_below_the_owner() {
+        // The owner itself keeps its own records
+        assert_eq!(
+            substitute_dname("example.com", "example.com", "example.net"),
+            None
+        );
+        // A suffix has to end on a label boundary
+        assert_eq!(
+            substitute_dname("notexample.com", "example.com", "example.net"),
+            None
+        );
+        assert_eq!(
+            substitute_dname("www.example.org", "example.com", "example.net"),
+            None
+        );
+    }
+
+    #[test]
+    fn dname_at_the_root_redirects_everything() {
+        assert_eq!(
+            substitute_dname("www.example.com", "", "example.net").as_deref(),
+            Some("www.example.com.example.net")
+        );
+    }
+
+    #[test]
+    fn dname_leaves_names_that_would_be_too_long() {
+        let name = format!("{}.example.com", vec!["a".repeat(63); 3].join("."));
+        let target = "b".repeat(63);
+
+        assert!(encoded_len(&name) <= 255);
+        assert_eq!(substitute_dname(&name, "example.com", &target), None);
+    }
+
+    /// Published IDNs and their A-labels
+    const IDNS: [(&str, &str); 4] = [
+        ("münchen.de", "xn--mnchen-3ya.de"),
+        ("bücher.example", "xn--bcher-kva.example"),
+        ("例え.テスト", "xn--r8jz45g.xn--zckzah"),
+        ("他们为什么不说中文", "xn--ihqwcrb4cv8a8dqg056pqjye"),
+    ];
+
+    #[test]
+    fn idns_are_encoded_to_a_labels() {
+        for (idn, ace) in IDNS {
+            assert_eq!(to_ascii(idn), ace);
+        }
+        assert_eq!(to_ascii("Bücher.example"), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn a_labels_are_decoded() {
+        for (idn, ace) in IDNS {
+            assert_eq!(to_unicode(ace), idn);
+        }
+        assert_eq!(to_unicode("XN--bcher-kva.example"), "bücher.example");
+    }
+
+    #[test]
+    fn ascii_names_are_left_alone() {
+        assert!(matches!(to_ascii("www.example.com"), Cow::Borrowed(_)));
+        assert!(matches!(to_unicode("www.example.com"), Cow::Borrowed(_)));
+        // Not valid punycode, so kept as is
+        assert_eq!(to_unicode("xn--!!.example"), "xn--!!.example");
+    }
+
+    #[test]
+    fn hostnames_are_letters_digits_and_hyphens() {
+        assert!(is_hostname("www.example.com"));
+        assert!(is_hostname("a-1.Example.COM."));
+        assert!(is_hostname("*.example.com"));
+        assert!(is_hostname(""));
+
+        assert!(!is_hostname("under_score.example.com"));
+        assert!(!is_hostname("-start.example.com"));
+        assert!(!is_hostname("end-.example.com"));
+        assert!(!is_hostname("sp ace.example.com"));
+        assert!(!is_hostname("www.*.example.com"));
+    }
+
+    /// `find_match` over a fixed set of names
+    fn find_in(qname: &str, names: &[&str]) -> Option<String> {
+        find_match(qname, |name| names.contains(&name))
+    }
+
+    #[test]
+    fn exact_names_match_first() {
+        let names = ["www.example.com", "*.example.com"];
+
+        assert_eq!(
+            find_in("WWW.example.com.", &names).as_deref(),
+            Some("www.example.com")
+        );
+    }
+
+    #[test]
+    fn wildcards_only_match_below_their_parent() {
+        let names = ["*.example.com"];
+
+        assert_eq!(
+            find_in("a.b.example.com", &names).as_deref(),
+            Some("*.example.com")
+        );
+        assert_eq!(
+            find_in("b.example.com", &names).as_deref(),
+            Some("*.example.com")
+        );
+        assert_eq!(find_in("example.com", &names), None);
+        assert_eq!(find_in("example.net", &names), None);
+    }
+
+    #[test]
+    fn wildcards_stop_at_existing_names() {
+        let names = ["*.example.com", "b.example.com"];
+
+        assert_eq!(find_in("a.b.example.com", &names), None);
+        assert_eq!(
+            find_in("a.c.example.com", &names).as_deref(),
+            Some("*.example.com")
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Resolver for tokio applications. Follows the same steps as the
+//! server's blocking resolver, only the socket I/O differs.
+
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::time::Duration;
+
+use log::{debug, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinSet;
+use tokio::time;
+
+use crate::cache::Cache;
+use crate::error::{DnsError, Result};
+use crate::packet::{BytePacketBuffer, DNSPacket, DNSRecord, QueryType};
+use crate::resolver::{
+    cache_response, cached_response, extend_chain, next_step, Budget, Step, MAX_CNAME_CHAIN,
+    MAX_RECURSION_DEPTH,
+};
+use crate::roots;
+
+pub use crate::resolver::LOOKUP_TIMEOUT;
+
+/// How many queries to other servers a single lookup may take
+const MAX_UPSTREAM_QUERIES: usize = 50;
+
+/// UDP payload size advertised to nameservers through EDNS0
+const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// Resolves the name from the root servers the way
+/// `resolver::recursive_lookup` does, without blocking
+pub async fn recursive_lookup(qname: &str, qtype: QueryType, cache: &Cache) -> Result<DNSPacket> {
+    let ctx = Context {
+        cache: cache.clone(),
+        budget: Budget::new(MAX_UPSTREAM_QUERIES),
+    };
+    nested_lookup(qname, qtype, &ctx, 0).await
+}
+
+/// State shared by the lookups done for one call to `recursive_lookup`
+#[derive(Clone)]
+struct Context {
+    cache: Cache,
+    /// Upstream queries left, nested lookups draw from the same budget
+    budget: Budget,
+}
+
+/// `recursive_lookup` for a lookup nested `depth` levels deep
+async fn nested_lookup(
+    qname: &str,
+    qtype: QueryType,
+    ctx: &Context,
+    depth: usize,
+) -> Result<DNSPacket> {
+    if depth > MAX_RECURSION_DEPTH {
+        return Err(DnsError::RecursionLimit);
+    }
+
+    let mut response = resolve(qname, qtype, ctx, depth).await?;
+
+    for _ in 0..MAX_CNAME_CHAIN {
+        // Whatever a DNAME redirects is followed like any other alias
+        response.synthesize_cnames(qname);
+        let Some(target) = response.unresolved_cname(qname, qtype) else {
+            break;
+        };
+        debug!("Following CNAME from {} to {}", qname, target);
+
+        let result = resolve(&target, qtype, ctx, depth).await?;
+        if !extend_chain(&mut response, result) {
+            break;
+        }
+    }
+
+    Ok(response)
+}
+
+/// Resolves the name starting from the root servers,
+/// answering from and filling the cache along the way
+async fn resolve(qname: &str, qtype: QueryType, ctx: &Context, depth: usize) -> Result<DNSPacket> {
+    if let Some(response) = cached_response(qname, qtype, false, &ctx.cache) {
+        return Ok(response);
+    }
+
+    let mut servers = roots::rotation();
+    // Non-authoritative answer to fall back on should the
+    // referral that came with it lead nowhere
+    let mut fallback = None;
+
+    loop {
+        let response = match lookup_any(qname, qtype, &servers, ctx).await {
+            Ok(response) => response,
+            Err(e) => return fallback.ok_or(e),
+        };
+
+        servers = match next_step(qname, response, &mut fallback) {
+            Step::Done(response) => {
+                cache_response(qname, qtype, false, &response, &ctx.cache);
+                return Ok(response);
+            }
+            Step::Servers(servers) => servers,
+            Step::Resolve(hosts, response) => match resolve_any_ns(hosts, ctx, depth).await? {
+                Some(servers) => servers,
+                // Without an address for any of them there's nowhere
+                // to go, what the server said is all there is
+                None => return Ok(fallback.unwrap_or(response)),
+            },
+        };
+    }
+}
+
+/// Resolves a few name servers at the same time and returns the
+/// addresses of whichever one comes back first. Fails when none of
+/// them resolve because their lookups nested too deep.
+async fn resolve_any_ns(
+    hosts: Vec<String>,
+    ctx: &Context,
+    depth: usize,
+) -> Result<Option<Vec<IpAddr>>> {
+    let mut lookups = JoinSet::new();
+
+    for host in hosts {
+        lookups.spawn(resolve_ns(host, ctx.clone(), depth));
+    }
+
+    // Dropping the set aborts the lookups that are still running
+    let mut result = Ok(None);
+    while let Some(joined) = lookups.join_next().await {
+        match joined {
+            Ok(Ok(Some(addrs))) => return Ok(Some(addrs)),
+            Ok(Err(e)) => result = Err(e),
+            Ok(Ok(None)) | Err(_) => {}
+        }
+    }
+    result
+}
+
+/// Addresses a name server lookup comes up with, see `resolve_any_ns`
+type NsAddrs = Result<Option<Vec<IpAddr>>>;
+
+/// Boxed with an explicit `Send`, the compiler can't work that out
+/// itself for a future that may end up calling itself
+fn resolve_ns(
+    host: String,
+    ctx: Context,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = NsAddrs> + Send>> {
+    Box::pin(async move {
+        // Falls back to IPv6 for servers only reachable over it
+        for qtype in [QueryType::A, QueryType::AAAA] {
+            let addrs: Vec<IpAddr> = match nested_lookup(&host, qtype, &ctx, depth + 1).await {
+                Ok(response) => response.get_all_ip().collect(),
+                // Going deeper for IPv6 won't help
+                Err(DnsError::RecursionLimit) => return Err(DnsError::RecursionLimit),
+                Err(_) => continue,
+            };
+            if !addrs.is_empty() {
+                return Ok(Some(addrs));
+            }
+        }
+        Ok(None)
+    })
+}
+
+/// Query the servers in order, moving on to the next one
+/// whenever a server can't be reached or doesn't answer in time
+async fn lookup_any(
+    qname: &str,
+    qtype: QueryType,
+    servers: &[IpAddr],
+    ctx: &Context,
+) -> Result<DNSPacket> {
+    let mut last_err = None;
+    let mut last_response = None;
+
+    for &ns in servers {
+        debug!("Looking up {:?} {} with ns {}", qtype, qname, ns);
+        ctx.budget.spend()?;
+
+        match lookup(qname, qtype, SocketAddr::new(ns, 53), LOOKUP_TIMEOUT).await {
+            // Another server for the same zone may well do better
+            Ok(response) if response.is_server_failure() => {
+                warn!("Nameserver {} answered {}", ns, response.header.rescode);
+                last_response = Some(response);
+            }
+            Err(e) if e.is_retryable() => {
+                warn!("Nameserver {} failed: {}", ns, e);
+                last_err = Some(e);
+            }
+            result => return result,
+        }
+    }
+
+    // Every server failed, pass on what the last one said if anything
+    match last_response {
+        Some(response) => Ok(response),
+        None => Err(last_err.unwrap_or(DnsError::NoNameservers)),
+    }
+}
+
+/// Sends a single query to the server, retrying over TCP
+/// when the response doesn't fit in a datagram
+pub async fn lookup(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<DNSPacket> {
+    // On the same address family as the server
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+
+    let mut packet = DNSPacket::query(qname, qtype);
+    packet
+        .resources
+        .push(DNSRecord::opt_with_cookie(EDNS_PAYLOAD_SIZE));
+
+    socket.send_to(&packet.to_bytes()?, server).await?;
+
+    let mut resp_buffer = BytePacketBuffer::with_size(EDNS_PAYLOAD_SIZE as usize);
+    let len = time::timeout(timeout, socket.recv(&mut resp_buffer.buf))
+        .await
+        .map_err(|_| DnsError::Timeout)??;
+    resp_buffer.set_len(len);
+
+    let response = DNSPacket::from_buffer(&mut resp_buffer)?;
+
+    packet.validate_response(&response)?;
+
+    // Didn't fit in a datagram, retry over TCP
+    if response.header.truncated_message {
+        return time::timeout(timeout, lookup_tcp(qname, qtype, server))
+            .await
+            .map_err(|_| DnsError::Timeout)?;
+    }
+
+    Ok(response)
+}
+
+async fn lookup_tcp(qname: &str, qtype: QueryType, server: SocketAddr) -> Result<DNSPacket> {
+    let mut stream = TcpStream::connect(server).await?;
+
+    let mut packet = DNSPacket::query(qname, qtype);
+
+    // Framed the same as the blocking lookups, then sent at once
+    let mut message = Vec::new();
+    packet.write_tcp(&mut message)?;
+    stream.write_all(&message).await?;
+
+    let len = stream.read_u16().await? as usize;
+
+    let mut resp_buffer = BytePacketBuffer::with_size(len);
+    stream.read_exact(&mut resp_buffer.buf).await?;
+    resp_buffer.set_len(len);
+
+    let response = DNSPacket::from_buffer(&mut resp_buffer)?;
+
+    packet.validate_response(&response)?;
+
+    Ok(response)
+}
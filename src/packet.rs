@@ -1,17 +1,40 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+#![allow(clippy::upper_case_acronyms, clippy::identity_op)]
 
-type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+use log::debug;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
-#[derive(Debug)]
+use crate::error::{DnsError, Result};
+use crate::name;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSPacket {
     pub header: DNSHeader,
     pub questions: Vec<DNSQuestion>,
     pub answers: Vec<DNSRecord>,
     pub authorities: Vec<DNSRecord>,
     pub resources: Vec<DNSRecord>,
+    /// Write records with their host names as they are instead of
+    /// checking them, for passing on what other servers answered
+    pub raw_names: bool,
+}
+
+impl Default for DNSPacket {
+    fn default() -> Self {
+        DNSPacket::new()
+    }
 }
 
+/// Steps `synthesize_cnames` takes beyond the records already in the
+/// answers, a chain of DNAMEs could otherwise redirect names forever
+const MAX_SYNTHESIZED_CNAMES: usize = 8;
+
 impl DNSPacket {
     pub fn new() -> Self {
         DNSPacket {
@@ -20,10 +43,70 @@ impl DNSPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             resources: Vec::new(),
+            raw_names: false,
+        }
+    }
+
+    fn write_record(&self, rec: &DNSRecord, buffer: &mut BytePacketBuffer) -> Result<usize> {
+        if self.raw_names {
+            rec.write_raw(buffer)
+        } else {
+            rec.write(buffer)
+        }
+    }
+
+    /// Query for a single question with a random id and recursion desired
+    pub fn query(qname: &str, qtype: QueryType) -> Self {
+        let mut packet = DNSPacket::new();
+
+        packet.header.id = random_id();
+        packet.header.questions = 1;
+        packet.header.recursion_desired = true;
+        packet
+            .questions
+            .push(DNSQuestion::new(qname.to_string(), qtype));
+
+        packet
+    }
+
+    /// Parses a packet from raw bytes, e.g. a capture or a TCP stream.
+    /// Any input at all gives either a packet or an error, never a panic,
+    /// which makes this the entry point to fuzz.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 {
+            return Err(DnsError::Malformed(format!(
+                "{} bytes is too short for a header",
+                data.len()
+            )));
+        }
+        if data.len() > 0xFFFF {
+            return Err(DnsError::Malformed(format!(
+                "{} bytes is larger than any message",
+                data.len()
+            )));
         }
+
+        let mut buffer = BytePacketBuffer::with_size(data.len());
+        buffer.buf.copy_from_slice(data);
+        buffer.set_len(data.len());
+
+        DNSPacket::from_buffer(&mut buffer)
     }
 
     pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<Self> {
+        DNSPacket::read(buffer).inspect_err(|e| {
+            // Up to a line past where parsing stopped
+            let len = buffer.pos.saturating_add(16);
+            debug!(
+                "Failed to parse packet at offset {}: {}\n{}",
+                buffer.pos,
+                e,
+                buffer.hexdump(0, len)
+            );
+        })
+    }
+
+    fn read(buffer: &mut BytePacketBuffer) -> Result<Self> {
         let mut result = DNSPacket::new();
         result.header.read(buffer)?;
 
@@ -48,10 +131,31 @@ impl DNSPacket {
             result.resources.push(rec);
         }
 
+        // EDNS0 extends the header's rcode with 8 more bits
+        let extended = result.resources.iter().find_map(|record| match record {
+            DNSRecord::OPT { extended_rcode, .. } => Some(*extended_rcode),
+            _ => None,
+        });
+        if let Some(extended) = extended {
+            let low = result.header.rescode.to_num() & 0x0F;
+            result.header.rescode = ResultCode::from_num((extended as u16) << 4 | low);
+        }
+
         Ok(result)
     }
 
+    /// Moves the upper bits of the rcode into the OPT record, if there's
+    /// one, since the header only has room for the lower 4
+    fn split_rescode(&mut self) {
+        for record in self.resources.iter_mut() {
+            if let DNSRecord::OPT { extended_rcode, .. } = record {
+                *extended_rcode = (self.header.rescode.to_num() >> 4) as u8;
+            }
+        }
+    }
+
     pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.split_rescode();
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
         self.header.authoritative_entries = self.authorities.len() as u16;
@@ -63,27 +167,552 @@ impl DNSPacket {
             question.write(buffer)?;
         }
         for rec in &self.answers {
-            rec.write(buffer)?;
+            self.write_record(rec, buffer)?;
         }
         for rec in &self.authorities {
-            rec.write(buffer)?;
+            self.write_record(rec, buffer)?;
         }
         for rec in &self.resources {
-            rec.write(buffer)?;
+            self.write_record(rec, buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the packet into an owned buffer holding just the message
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::with_size(0xFFFF);
+        self.write(&mut buffer)?;
+
+        buffer.buf.truncate(buffer.pos);
+        Ok(buffer.buf)
+    }
+
+    /// Writes the packet to a TCP stream, prefixed with its length the
+    /// way messages over TCP are. Nothing is truncated, the message can
+    /// take up to the 64KB the prefix allows.
+    pub fn write_tcp<W: Write>(&mut self, stream: &mut W) -> Result<()> {
+        let data = self.to_bytes()?;
+
+        // Sent at once, a separate length segment can stall some servers
+        let mut message = Vec::with_capacity(2 + data.len());
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(&data);
+        stream.write_all(&message)?;
+
+        Ok(())
+    }
+
+    /// Reads a message sent over TCP behind its length
+    pub fn read_tcp<R: Read>(stream: &mut R) -> Result<DNSPacket> {
+        let mut len_buf = [0; 2];
+        stream.read_exact(&mut len_buf)?;
+        let len = u16::from_be_bytes(len_buf) as usize;
+
+        let mut buffer = BytePacketBuffer::with_size(len);
+        stream.read_exact(&mut buffer.buf)?;
+        buffer.set_len(len);
+
+        DNSPacket::from_buffer(&mut buffer)
+    }
+
+    /// Like `write`, but records that don't fit in the buffer are dropped
+    /// instead of failing the whole packet. The truncated flag is set so
+    /// the client knows to retry over TCP.
+    pub fn write_truncated(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.split_rescode();
+        self.header.questions = self.questions.len() as u16;
+        self.header.write(buffer)?;
+
+        for question in &self.questions {
+            question.write(buffer)?;
+        }
+
+        // The OPT record goes last and is kept however little else fits,
+        // it carries the upper rcode bits and the client's EDNS0 state
+        let opt = self
+            .resources
+            .iter()
+            .position(|rec| matches!(rec, DNSRecord::OPT { .. }))
+            .map(|i| self.resources.remove(i));
+
+        let mut truncated = false;
+        // Section and start of each record written, in order
+        let mut written: Vec<(usize, usize)> = Vec::new();
+        let sections = [&self.answers, &self.authorities, &self.resources];
+
+        'sections: for (i, section) in sections.iter().enumerate() {
+            for rec in section.iter() {
+                let pos = buffer.pos;
+                match self.write_record(rec, buffer) {
+                    Ok(_) => written.push((i, pos)),
+                    Err(DnsError::EndOfBuffer) => {
+                        // Roll back the partially written record
+                        buffer.truncate_to(pos)?;
+                        truncated = true;
+                        break 'sections;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        if let Some(opt) = &opt {
+            loop {
+                let pos = buffer.pos;
+                match opt.write(buffer) {
+                    Ok(_) => break,
+                    Err(DnsError::EndOfBuffer) => {
+                        // Give up the last record written to make room
+                        let (_, start) = written.pop().ok_or(DnsError::EndOfBuffer)?;
+                        buffer.truncate_to(start.min(pos))?;
+                        truncated = true;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let mut counts = [0; 3];
+        for (i, _) in &written {
+            counts[*i] += 1;
+        }
+
+        if truncated {
+            self.header.truncated_message = true;
+            self.answers.truncate(counts[0]);
+            self.authorities.truncate(counts[1]);
+            self.resources.truncate(counts[2]);
+        }
+        if let Some(opt) = opt {
+            self.resources.push(opt);
+            counts[2] += 1;
         }
 
+        self.header.answers = counts[0] as u16;
+        self.header.authoritative_entries = counts[1] as u16;
+        self.header.resource_entries = counts[2] as u16;
+
+        // Counts are only known now, go back and patch the header
+        let end = buffer.pos;
+        buffer.set_position(0)?;
+        self.header.write(buffer)?;
+        buffer.set_position(end)?;
+
         Ok(())
     }
 }
 
+/// Unpredictable query id, so responses can't be trivially spoofed.
+/// Each RandomState is seeded differently, which is enough entropy
+/// without pulling in a dedicated crate.
+fn random_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
 impl DNSPacket {
+    /// Puts the A records in the answers in random order among
+    /// themselves, and the AAAA records likewise, so clients that
+    /// always take the first address spread out over all of them.
+    /// Everything else, such as the CNAMEs leading up, stays put.
+    pub fn shuffle_addresses(&mut self) {
+        for qtype in [QueryType::A, QueryType::AAAA] {
+            let positions: Vec<usize> = (0..self.answers.len())
+                .filter(|&i| self.answers[i].query_type() == qtype)
+                .collect();
+
+            // Fisher-Yates over just those positions
+            for i in (1..positions.len()).rev() {
+                let j = (RandomState::new().build_hasher().finish() % (i as u64 + 1)) as usize;
+                self.answers.swap(positions[i], positions[j]);
+            }
+        }
+    }
+
+    /// Whether this is a question rather than an answer to one
+    pub fn is_query(&self) -> bool {
+        !self.header.response
+    }
+
+    /// Adds the records of `other` that this packet doesn't have yet,
+    /// section by section. An OPT record is only taken over when there
+    /// isn't one already, a message can't carry two. The header is left
+    /// alone, `write` fills in the counts.
+    pub fn merge(&mut self, other: DNSPacket) {
+        let is_opt = |record: &DNSRecord| matches!(record, DNSRecord::OPT { .. });
+
+        let sections = [
+            (&mut self.answers, other.answers),
+            (&mut self.authorities, other.authorities),
+            (&mut self.resources, other.resources),
+        ];
+        for (section, records) in sections {
+            for record in records {
+                if is_opt(&record) && section.iter().any(is_opt) {
+                    continue;
+                }
+                if !section.contains(&record) {
+                    section.push(record);
+                }
+            }
+        }
+    }
+
+    /// Whether the response says the name doesn't exist (NXDOMAIN) or
+    /// has no records of the type asked for (NODATA). NODATA comes as
+    /// an empty NOERROR response with an SOA, which sets it apart from
+    /// a referral carrying NS records.
+    pub fn is_negative(&self) -> bool {
+        match self.header.rescode {
+            ResultCode::NXDOMAIN => true,
+            ResultCode::NOERROR => {
+                self.answers.is_empty()
+                    && self
+                        .authorities
+                        .iter()
+                        .any(|record| matches!(record, DNSRecord::SOA { .. }))
+            }
+            _ => false,
+        }
+    }
+
+    /// First A record in the answers section, e.g. the
+    /// address of a name server that was just resolved
     pub fn get_random_a(&self) -> Option<Ipv4Addr> {
-        self.answers.iter().find_map(|record| match record {
-            DNSRecord::A { addr, .. } => Some(*addr),
+        self.get_all_a().next()
+    }
+
+    /// Every address in the answers section, e.g. all
+    /// the addresses a name server can be reached on
+    pub fn get_all_a(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.a_records().map(|(_, addr)| addr)
+    }
+
+    /// First AAAA record in the answers section
+    pub fn get_random_aaaa(&self) -> Option<Ipv6Addr> {
+        self.get_all_aaaa().next()
+    }
+
+    pub fn get_all_aaaa(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.aaaa_records().map(|(_, addr)| addr)
+    }
+
+    /// Every address in the answers section, A or AAAA
+    pub fn get_all_ip(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.answers.iter().filter_map(|record| match record {
+            DNSRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+            DNSRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })
+    }
+
+    /// Adds the CNAMEs that DNAME records in the answers imply for the
+    /// names along the alias chain starting at `qname`, where the server
+    /// left them out (RFC 6672). Servers that know DNAME include them
+    /// themselves, followed CNAMEs are only synthesized once.
+    pub fn synthesize_cnames(&mut self, qname: &str) {
+        let mut name = qname.to_string();
+
+        // Bounded in case the aliases point at each other
+        for _ in 0..self.answers.len() + MAX_SYNTHESIZED_CNAMES {
+            let cname = self.answers.iter().find_map(|record| match record {
+                DNSRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(&name) => {
+                    Some(host.clone())
+                }
+                _ => None,
+            });
+            if let Some(host) = cname {
+                name = host;
+                continue;
+            }
+
+            let synthesized = self.answers.iter().find_map(|record| match record {
+                DNSRecord::DNAME {
+                    domain,
+                    class,
+                    target,
+                    ttl,
+                } => name::substitute_dname(&name, domain, target).map(|host| DNSRecord::CNAME {
+                    domain: name.clone(),
+                    class: *class,
+                    host,
+                    ttl: *ttl,
+                }),
+                _ => None,
+            });
+            let Some(cname) = synthesized else {
+                break;
+            };
+            if let DNSRecord::CNAME { host, .. } = &cname {
+                name = host.clone();
+            }
+            self.answers.push(cname);
+        }
+    }
+
+    /// Name a CNAME chain in the answers ends at when nothing of the
+    /// requested type was returned for it, so it still needs a lookup
+    pub fn unresolved_cname(&self, qname: &str, qtype: QueryType) -> Option<String> {
+        if qtype == QueryType::CNAME || qtype == QueryType::ANY {
+            return None;
+        }
+
+        let mut name = qname;
+        // Bounded in case the aliases point at each other
+        for _ in 0..self.answers.len() {
+            let next = self.answers.iter().find_map(|record| match record {
+                DNSRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(name) => {
+                    Some(host.as_str())
+                }
+                _ => None,
+            });
+            match next {
+                Some(host) => name = host,
+                None => break,
+            }
+        }
+
+        let answered = self.answers.iter().any(|record| {
+            record.query_type() == qtype && record.domain().eq_ignore_ascii_case(name)
+        });
+        if name.eq_ignore_ascii_case(qname) || answered {
+            return None;
+        }
+
+        Some(name.to_string())
+    }
+
+    /// Makes sure the response actually answers this query
+    pub fn validate_response(&self, response: &DNSPacket) -> Result<()> {
+        if response.header.id != self.header.id {
+            return Err(DnsError::IdMismatch {
+                expected: self.header.id,
+                got: response.header.id,
+            });
+        }
+
+        // Without a question there's nothing the response could answer
+        let sent = self
+            .questions
+            .first()
+            .ok_or_else(|| DnsError::Malformed("Query has no question".into()))?;
+        if !response.questions.first().is_some_and(|q| q.matches(sent)) {
+            return Err(DnsError::QuestionMismatch {
+                expected: format!("{:?} {}", sent.qtype, sent.name),
+                got: response
+                    .questions
+                    .first()
+                    .map(|q| format!("{:?} {}", q.qtype, q.name))
+                    .unwrap_or_else(|| "<none>".to_string()),
+            });
+        }
+
+        // Only someone who saw the query knows the client cookie, a
+        // server that doesn't do cookies just leaves the option out
+        if let (Some(sent), Some(got)) = (self.cookie(), response.cookie()) {
+            if got.get(..8) != sent.get(..8) {
+                return Err(DnsError::CookieMismatch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Value of the COOKIE option in the OPT record, if there is one
+    pub fn cookie(&self) -> Option<&[u8]> {
+        self.resources.iter().find_map(|record| match record {
+            DNSRecord::OPT { options, .. } => options
+                .iter()
+                .find(|(code, _)| *code == COOKIE_OPTION)
+                .map(|(_, value)| value.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Records of the given type in the answers section, e.g. the MX
+    /// records out of the mix an ANY query returns
+    pub fn answers_for(&self, qtype: QueryType) -> Vec<&DNSRecord> {
+        self.answers
+            .iter()
+            .filter(|record| record.query_type().to_num() == qtype.to_num())
+            .collect()
+    }
+
+    /// A records in the answers section as (domain, address)
+    pub fn a_records(&self) -> impl Iterator<Item = (&str, Ipv4Addr)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::A { domain, addr, .. } => Some((domain.as_str(), *addr)),
+            _ => None,
+        })
+    }
+
+    /// AAAA records in the answers section as (domain, address)
+    pub fn aaaa_records(&self) -> impl Iterator<Item = (&str, Ipv6Addr)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::AAAA { domain, addr, .. } => Some((domain.as_str(), *addr)),
+            _ => None,
+        })
+    }
+
+    /// NS records in the answers section as (domain, host)
+    pub fn ns_records(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::NS { domain, host, .. } => Some((domain.as_str(), host.as_str())),
+            _ => None,
+        })
+    }
+
+    /// CNAME records in the answers section as (alias, canonical name)
+    pub fn cname_records(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::CNAME { domain, host, .. } => Some((domain.as_str(), host.as_str())),
+            _ => None,
+        })
+    }
+
+    /// MX records in the answers section as (domain, priority, host)
+    pub fn mx_records(&self) -> impl Iterator<Item = (&str, u16, &str)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::MX {
+                domain,
+                priority,
+                host,
+                ..
+            } => Some((domain.as_str(), *priority, host.as_str())),
+            _ => None,
+        })
+    }
+
+    /// TXT records in the answers section as (domain, text)
+    pub fn txt_records(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::TXT { domain, text, .. } => Some((domain.as_str(), text.as_str())),
+            _ => None,
+        })
+    }
+
+    /// PTR records in the answers section as (domain, host)
+    pub fn ptr_records(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.answers_matching(|record| match record {
+            DNSRecord::PTR { domain, host, .. } => Some((domain.as_str(), host.as_str())),
             _ => None,
         })
     }
 
+    fn answers_matching<'a, T>(
+        &'a self,
+        f: impl Fn(&'a DNSRecord) -> Option<T> + 'a,
+    ) -> impl Iterator<Item = T> + 'a {
+        self.answers.iter().filter_map(f)
+    }
+
+    /// Whether the server couldn't or wouldn't answer, as opposed
+    /// to answering that there's nothing there
+    pub fn is_server_failure(&self) -> bool {
+        matches!(
+            self.header.rescode,
+            ResultCode::SERVFAIL | ResultCode::REFUSED
+        )
+    }
+
+    /// Checks the invariants a packet should hold before it's sent:
+    /// header counts match the sections, names fit in 255 bytes and
+    /// records of unknown types only show up when they were asked for
+    pub fn validate(&self) -> Result<()> {
+        let counts = [
+            ("questions", self.header.questions, self.questions.len()),
+            ("answers", self.header.answers, self.answers.len()),
+            (
+                "authorities",
+                self.header.authoritative_entries,
+                self.authorities.len(),
+            ),
+            (
+                "resources",
+                self.header.resource_entries,
+                self.resources.len(),
+            ),
+        ];
+        for (section, count, len) in counts {
+            if count as usize != len {
+                return Err(DnsError::Malformed(format!(
+                    "header counts {} {} but there are {}",
+                    count, section, len
+                )));
+            }
+        }
+
+        let records = || {
+            self.answers
+                .iter()
+                .chain(&self.authorities)
+                .chain(&self.resources)
+        };
+
+        let names = self
+            .questions
+            .iter()
+            .map(|question| question.name.as_str())
+            .chain(records().map(|record| record.domain()));
+        for name in names {
+            if name::encoded_len(name) > 255 {
+                return Err(DnsError::NameTooLong);
+            }
+        }
+
+        let asked_unknown = self
+            .questions
+            .iter()
+            .any(|question| matches!(question.qtype, QueryType::UNKNOWN(_)));
+        if !asked_unknown {
+            if let Some(record) =
+                records().find(|record| matches!(record, DNSRecord::UNKNOWN { .. }))
+            {
+                return Err(DnsError::Malformed(format!(
+                    "{:?} record for {} wasn't asked for",
+                    record.query_type(),
+                    record.domain()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the sender wants DNSSEC records, the DO bit of its OPT record
+    pub fn dnssec_ok(&self) -> bool {
+        self.resources.iter().any(|record| {
+            matches!(
+                record,
+                DNSRecord::OPT {
+                    dnssec_ok: true,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// Largest UDP response the sender can take, as advertised in its
+    /// OPT record. Without EDNS0 it's the classic 512 bytes.
+    pub fn max_payload_size(&self) -> usize {
+        self.resources
+            .iter()
+            .find_map(|record| match record {
+                DNSRecord::OPT {
+                    udp_payload_size, ..
+                } => Some(*udp_payload_size as usize),
+                _ => None,
+            })
+            .unwrap_or(512)
+            .max(512)
+    }
+
+    /// Whether a server that isn't authoritative for `qname` points to
+    /// the servers of a zone holding it, which know better than it does
+    pub fn is_referral(&self, qname: &str) -> bool {
+        !self.header.authoritative_answer && self.get_ns(qname).next().is_some()
+    }
+
     /// Returns an iterator of all name servers in the authorities
     /// section as (domain, host) tuples
     fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
@@ -93,115 +722,609 @@ impl DNSPacket {
                 DNSRecord::NS { domain, host, .. } => Some((domain.as_str(), host.as_str())),
                 _ => None,
             })
-            .filter(move |(domain, _)| qname.ends_with(*domain))
+            .filter(move |(domain, _)| name::is_subdomain(qname, domain))
+    }
+
+    /// Use when A or AAAA records come bundled together with the
+    /// NS records. Saves additional lookups.
+    ///
+    /// Matches the NS records for `qname` in the authorities section
+    /// against the glue records in the additional section.
+    pub fn get_resolved_ns(&self, qname: &str) -> Option<IpAddr> {
+        self.get_all_resolved_ns(qname).next()
     }
 
-    /// Use when A records come bundled together with the NS records.
-    /// Saves additional lookups.
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
+    /// Every name server address bundled with the NS records, so
+    /// there's something to fall back on when one doesn't answer
+    pub fn get_all_resolved_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = IpAddr> + 'a {
         self.get_ns(qname)
-            // Looks for matching A records in the additional section
-            .flat_map(|(_, host)| {
+            // Looks for matching A and AAAA records in the additional section
+            .flat_map(move |(_, host)| {
                 self.resources
                     .iter()
                     .filter_map(move |record| match record {
-                        DNSRecord::A { domain, addr, .. } if domain == host => Some(addr),
+                        DNSRecord::A { domain, addr, .. } if domain.eq_ignore_ascii_case(host) => {
+                            Some(IpAddr::V4(*addr))
+                        }
+                        DNSRecord::AAAA { domain, addr, .. }
+                            if domain.eq_ignore_ascii_case(host) =>
+                        {
+                            Some(IpAddr::V6(*addr))
+                        }
                         _ => None,
                     })
             })
-            .map(|addr| *addr)
-            .next()
     }
 
-    /// Use when there are no A records bundled with the NS records
-    /// to perform an additional lookup to the name server
+    /// Use when there are no glue records bundled with the NS records
+    /// to perform an additional lookup to the name server.
+    ///
+    /// Returns the host name of the first NS record for `qname`.
     pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
-        self.get_ns(qname).map(|(_, host)| host).next()
+        self.get_all_unresolved_ns(qname).next()
+    }
+
+    /// Every name server host in the authorities section,
+    /// for resolving a few of them at once
+    pub fn get_all_unresolved_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = &'a str> {
+        self.get_ns(qname).map(|(_, host)| host)
+    }
+}
+
+/// Summary in the style of dig's output, the alternate `{:#}`
+/// form shows internationalized names in Unicode
+impl fmt::Display for DNSPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unicode = f.alternate();
+        let header = &self.header;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            header.opcode,
+            header.rescode.to_str(),
+            header.id
+        )?;
+
+        let flags = [
+            (header.response, "qr"),
+            (header.authoritative_answer, "aa"),
+            (header.truncated_message, "tc"),
+            (header.recursion_desired, "rd"),
+            (header.recursion_available, "ra"),
+            (header.authed_data, "ad"),
+            (header.checking_disabled, "cd"),
+        ];
+        write!(f, ";; flags:")?;
+        for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, " {}", flag)?;
+        }
+        // Counted from the sections, the header's may not be set yet
+        writeln!(
+            f,
+            "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.resources.len()
+        )?;
+
+        let (opt, additional): (Vec<_>, Vec<_>) = self
+            .resources
+            .iter()
+            .partition(|record| matches!(record, DNSRecord::OPT { .. }));
+
+        if let Some(record) = opt.first() {
+            write!(f, "\n;; OPT PSEUDOSECTION:\n{}\n", record)?;
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                if unicode {
+                    writeln!(f, "{:#}", question)?;
+                } else {
+                    writeln!(f, "{}", question)?;
+                }
+            }
+        }
+
+        let sections = [
+            ("ANSWER", self.answers.iter().collect::<Vec<_>>()),
+            ("AUTHORITY", self.authorities.iter().collect()),
+            ("ADDITIONAL", additional),
+        ];
+        for (title, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", title)?;
+            for record in records {
+                if unicode {
+                    writeln!(f, "{:#}", record)?;
+                } else {
+                    writeln!(f, "{}", record)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Name as written in zone files, with the trailing dot. `unicode`
+/// decodes `xn--` labels, for the alternate `{:#}` form.
+fn fqdn(name: &str, unicode: bool) -> String {
+    let name = if unicode {
+        name::to_unicode(name)
+    } else {
+        Cow::Borrowed(name)
+    };
+
+    if name.ends_with('.') {
+        name.into_owned()
+    } else {
+        format!("{}.", name)
     }
 }
 
-#[derive(Debug)]
+/// Character string in quotes, with anything unprintable escaped
+/// as `\DDD` the way zone files have it
+fn quoted(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            b' '..=b'~' => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DNSRecord {
+    /// Record of a type without its own variant, with the RDATA as is
     UNKNOWN {
         domain: String,
+        class: QueryClass,
         qtype: u16,
-        data_len: u16,
+        data: Vec<u8>,
         ttl: u32,
     },
     A {
         domain: String,
+        class: QueryClass,
         addr: Ipv4Addr,
         ttl: u32,
     },
     NS {
         domain: String,
+        class: QueryClass,
         host: String,
         ttl: u32,
     },
     CNAME {
         domain: String,
+        class: QueryClass,
         host: String,
         ttl: u32,
     },
+    /// Alias for every name below `domain` (RFC 6672), the names under
+    /// `target` with the same labels in front
+    DNAME {
+        domain: String,
+        class: QueryClass,
+        target: String,
+        ttl: u32,
+    },
     MX {
         domain: String,
+        class: QueryClass,
         priority: u16,
         host: String,
         ttl: u32,
     },
     AAAA {
         domain: String,
+        class: QueryClass,
         addr: Ipv6Addr,
         ttl: u32,
     },
-}
-
-impl DNSRecord {
-    pub fn read(buffer: &mut BytePacketBuffer) -> Result<DNSRecord> {
-        let mut domain = String::new();
-        buffer.read_qname(&mut domain)?;
-
-        let qtype_num = buffer.read_u16()?;
-        let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
-        let ttl = buffer.read_u32()?;
-        let data_len = buffer.read_u16()?;
-
-        match qtype {
-            QueryType::A => {
-                let addr = buffer.read_u32()?;
-                let addr = Ipv4Addr::new(
-                    ((addr >> 24) & 0xFF) as u8,
-                    ((addr >> 16) & 0xFF) as u8,
-                    ((addr >> 8) & 0xFF) as u8,
+    TXT {
+        domain: String,
+        class: QueryClass,
+        text: String,
+        ttl: u32,
+    },
+    SOA {
+        domain: String,
+        class: QueryClass,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        class: QueryClass,
+        host: String,
+        ttl: u32,
+    },
+    SRV {
+        domain: String,
+        class: QueryClass,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+        ttl: u32,
+    },
+    CAA {
+        domain: String,
+        class: QueryClass,
+        flags: u8,
+        tag: String,
+        value: String,
+        ttl: u32,
+    },
+    /// Signature over the `type_covered` records at the name (RFC 4034),
+    /// kept for inspection rather than validated
+    RRSIG {
+        domain: String,
+        class: QueryClass,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        signature: Vec<u8>,
+        ttl: u32,
+    },
+    /// Public key of a signed zone (RFC 4034)
+    DNSKEY {
+        domain: String,
+        class: QueryClass,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    },
+    /// Location of the name (RFC 1876). Sizes and precisions are in
+    /// the encoded mantissa and exponent form, coordinates in
+    /// thousandths of an arc second offset by 2^31 so the equator and
+    /// prime meridian sit in the middle, altitude in centimeters above
+    /// 100km below the WGS 84 spheroid.
+    LOC {
+        domain: String,
+        class: QueryClass,
+        version: u8,
+        size: u8,
+        horiz_pre: u8,
+        vert_pre: u8,
+        latitude: u32,
+        longitude: u32,
+        altitude: u32,
+        ttl: u32,
+    },
+    /// Naming authority pointer (RFC 3403), rewrite rules for ENUM and SIP
+    NAPTR {
+        domain: String,
+        class: QueryClass,
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: String,
+        ttl: u32,
+    },
+    /// Service binding (RFC 9460), `params` holds the SvcParams as
+    /// key and raw value pairs in wire order
+    SVCB {
+        domain: String,
+        class: QueryClass,
+        priority: u16,
+        target: String,
+        params: Vec<(u16, Vec<u8>)>,
+        ttl: u32,
+    },
+    /// SVCB for HTTPS origins, same layout under its own type
+    HTTPS {
+        domain: String,
+        class: QueryClass,
+        priority: u16,
+        target: String,
+        params: Vec<(u16, Vec<u8>)>,
+        ttl: u32,
+    },
+    /// EDNS0 pseudo-record, lives in the additional section
+    /// and repurposes the class and TTL fields
+    OPT {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        /// EDNS options as code and raw value pairs, e.g. a `COOKIE_OPTION`
+        options: Vec<(u16, Vec<u8>)>,
+    },
+}
+
+/// EDNS option carrying DNS cookies (RFC 7873), an 8 byte client
+/// cookie optionally followed by the server's
+pub const COOKIE_OPTION: u16 = 10;
+
+impl DNSRecord {
+    /// Plain EDNS0 record advertising the given UDP payload size
+    pub fn opt(udp_payload_size: u16) -> DNSRecord {
+        DNSRecord::OPT {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    /// EDNS0 record carrying a fresh client cookie, so a response can
+    /// be told apart from one spoofed by someone who didn't see the query
+    pub fn opt_with_cookie(udp_payload_size: u16) -> DNSRecord {
+        let cookie = RandomState::new().build_hasher().finish().to_be_bytes();
+
+        DNSRecord::OPT {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![(COOKIE_OPTION, cookie.to_vec())],
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        match *self {
+            DNSRecord::UNKNOWN { ref domain, .. }
+            | DNSRecord::A { ref domain, .. }
+            | DNSRecord::NS { ref domain, .. }
+            | DNSRecord::CNAME { ref domain, .. }
+            | DNSRecord::DNAME { ref domain, .. }
+            | DNSRecord::MX { ref domain, .. }
+            | DNSRecord::AAAA { ref domain, .. }
+            | DNSRecord::TXT { ref domain, .. }
+            | DNSRecord::SOA { ref domain, .. }
+            | DNSRecord::PTR { ref domain, .. }
+            | DNSRecord::SRV { ref domain, .. }
+            | DNSRecord::NAPTR { ref domain, .. }
+            | DNSRecord::RRSIG { ref domain, .. }
+            | DNSRecord::DNSKEY { ref domain, .. }
+            | DNSRecord::LOC { ref domain, .. }
+            | DNSRecord::SVCB { ref domain, .. }
+            | DNSRecord::HTTPS { ref domain, .. }
+            | DNSRecord::CAA { ref domain, .. } => domain,
+            // Always the root
+            DNSRecord::OPT { .. } => "",
+        }
+    }
+
+    pub fn set_domain(&mut self, name: &str) {
+        match self {
+            DNSRecord::UNKNOWN { domain, .. }
+            | DNSRecord::A { domain, .. }
+            | DNSRecord::NS { domain, .. }
+            | DNSRecord::CNAME { domain, .. }
+            | DNSRecord::DNAME { domain, .. }
+            | DNSRecord::MX { domain, .. }
+            | DNSRecord::AAAA { domain, .. }
+            | DNSRecord::TXT { domain, .. }
+            | DNSRecord::SOA { domain, .. }
+            | DNSRecord::PTR { domain, .. }
+            | DNSRecord::SRV { domain, .. }
+            | DNSRecord::NAPTR { domain, .. }
+            | DNSRecord::RRSIG { domain, .. }
+            | DNSRecord::DNSKEY { domain, .. }
+            | DNSRecord::LOC { domain, .. }
+            | DNSRecord::SVCB { domain, .. }
+            | DNSRecord::HTTPS { domain, .. }
+            | DNSRecord::CAA { domain, .. } => *domain = name.to_string(),
+            DNSRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        match *self {
+            DNSRecord::UNKNOWN { qtype, .. } => QueryType::UNKNOWN(qtype),
+            DNSRecord::A { .. } => QueryType::A,
+            DNSRecord::NS { .. } => QueryType::NS,
+            DNSRecord::CNAME { .. } => QueryType::CNAME,
+            DNSRecord::DNAME { .. } => QueryType::DNAME,
+            DNSRecord::MX { .. } => QueryType::MX,
+            DNSRecord::AAAA { .. } => QueryType::AAAA,
+            DNSRecord::TXT { .. } => QueryType::TXT,
+            DNSRecord::SOA { .. } => QueryType::SOA,
+            DNSRecord::PTR { .. } => QueryType::PTR,
+            DNSRecord::SRV { .. } => QueryType::SRV,
+            DNSRecord::CAA { .. } => QueryType::CAA,
+            DNSRecord::NAPTR { .. } => QueryType::NAPTR,
+            DNSRecord::RRSIG { .. } => QueryType::RRSIG,
+            DNSRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DNSRecord::LOC { .. } => QueryType::LOC,
+            DNSRecord::SVCB { .. } => QueryType::SVCB,
+            DNSRecord::HTTPS { .. } => QueryType::HTTPS,
+            DNSRecord::OPT { .. } => QueryType::OPT,
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            DNSRecord::UNKNOWN { ttl, .. }
+            | DNSRecord::A { ttl, .. }
+            | DNSRecord::NS { ttl, .. }
+            | DNSRecord::CNAME { ttl, .. }
+            | DNSRecord::DNAME { ttl, .. }
+            | DNSRecord::MX { ttl, .. }
+            | DNSRecord::AAAA { ttl, .. }
+            | DNSRecord::TXT { ttl, .. }
+            | DNSRecord::SOA { ttl, .. }
+            | DNSRecord::PTR { ttl, .. }
+            | DNSRecord::SRV { ttl, .. }
+            | DNSRecord::NAPTR { ttl, .. }
+            | DNSRecord::RRSIG { ttl, .. }
+            | DNSRecord::DNSKEY { ttl, .. }
+            | DNSRecord::LOC { ttl, .. }
+            | DNSRecord::SVCB { ttl, .. }
+            | DNSRecord::HTTPS { ttl, .. }
+            | DNSRecord::CAA { ttl, .. } => ttl,
+            // Not cacheable
+            DNSRecord::OPT { .. } => 0,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DNSRecord::UNKNOWN { ttl, .. }
+            | DNSRecord::A { ttl, .. }
+            | DNSRecord::NS { ttl, .. }
+            | DNSRecord::CNAME { ttl, .. }
+            | DNSRecord::DNAME { ttl, .. }
+            | DNSRecord::MX { ttl, .. }
+            | DNSRecord::AAAA { ttl, .. }
+            | DNSRecord::TXT { ttl, .. }
+            | DNSRecord::SOA { ttl, .. }
+            | DNSRecord::PTR { ttl, .. }
+            | DNSRecord::SRV { ttl, .. }
+            | DNSRecord::NAPTR { ttl, .. }
+            | DNSRecord::RRSIG { ttl, .. }
+            | DNSRecord::DNSKEY { ttl, .. }
+            | DNSRecord::LOC { ttl, .. }
+            | DNSRecord::SVCB { ttl, .. }
+            | DNSRecord::HTTPS { ttl, .. }
+            | DNSRecord::CAA { ttl, .. } => *ttl = new_ttl,
+            DNSRecord::OPT { .. } => {}
+        }
+    }
+
+    /// Copy of the record with the TTL replaced, e.g. with
+    /// whatever lifetime it has left in a cache
+    pub fn with_ttl(&self, new_ttl: u32) -> DNSRecord {
+        let mut record = self.clone();
+        record.set_ttl(new_ttl);
+        record
+    }
+
+    /// OPT record with the DO bit set as given, asking for DNSSEC
+    /// records to be included. Other records are returned as they are.
+    pub fn with_dnssec_ok(mut self, value: bool) -> DNSRecord {
+        if let DNSRecord::OPT { dnssec_ok, .. } = &mut self {
+            *dnssec_ok = value;
+        }
+        self
+    }
+
+    /// Checks the names that identify hosts against the letters, digits
+    /// and hyphen rule: the owners of A and AAAA records and the NS,
+    /// CNAME, MX, PTR and SRV targets. `write` does this first,
+    /// `write_raw` passes records from other servers on as they're named.
+    pub fn check_hostnames(&self) -> Result<()> {
+        match self {
+            DNSRecord::A { domain, .. } | DNSRecord::AAAA { domain, .. } => check_hostname(domain),
+            DNSRecord::NS { host, .. }
+            | DNSRecord::CNAME { host, .. }
+            | DNSRecord::MX { host, .. }
+            | DNSRecord::PTR { host, .. } => check_hostname(host),
+            DNSRecord::SRV { target, .. } => check_hostname(target),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<DNSRecord> {
+        let domain = buffer.read_name()?;
+
+        let qtype_num = buffer.read_u16()?;
+        let qtype = QueryType::from_num(qtype_num);
+        let class_num = buffer.read_u16()?;
+        let class = QueryClass::from_num(class_num);
+        let ttl = buffer.read_u32()?;
+        let data_len = buffer.read_u16()?;
+
+        // Everything below trusts data_len to stay within the message
+        let remaining = buffer.len - buffer.pos;
+        if data_len as usize > remaining {
+            return Err(DnsError::Malformed(format!(
+                "{} record claims {} bytes of data but only {} remain",
+                qtype, data_len, remaining
+            )));
+        }
+        let start = buffer.pos;
+        let end = start + data_len as usize;
+
+        let record = match qtype {
+            QueryType::A => {
+                let addr = buffer.read_u32()?;
+                let addr = Ipv4Addr::new(
+                    ((addr >> 24) & 0xFF) as u8,
+                    ((addr >> 16) & 0xFF) as u8,
+                    ((addr >> 8) & 0xFF) as u8,
                     ((addr >> 0) & 0xFF) as u8,
                 );
 
-                Ok(DNSRecord::A { domain, addr, ttl })
+                DNSRecord::A {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                }
             }
             QueryType::NS => {
-                let mut host = String::new();
-                buffer.read_qname(&mut host)?;
+                let host = buffer.read_name()?;
 
-                Ok(DNSRecord::NS { domain, host, ttl })
+                DNSRecord::NS {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                }
             }
             QueryType::CNAME => {
-                let mut host = String::new();
-                buffer.read_qname(&mut host)?;
+                let host = buffer.read_name()?;
+
+                DNSRecord::CNAME {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                }
+            }
+            QueryType::DNAME => {
+                let target = buffer.read_name()?;
 
-                Ok(DNSRecord::CNAME { domain, host, ttl })
+                DNSRecord::DNAME {
+                    domain,
+                    class,
+                    target,
+                    ttl,
+                }
             }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
-                let mut host = String::new();
-                buffer.read_qname(&mut host)?;
+                let host = buffer.read_name()?;
 
-                Ok(DNSRecord::MX {
+                DNSRecord::MX {
                     domain,
+                    class,
                     priority,
                     host,
                     ttl,
-                })
+                }
             }
             QueryType::AAAA => {
                 let addr1 = buffer.read_u32()?;
@@ -219,33 +1342,287 @@ impl DNSRecord {
                     ((addr4 >> 0) & 0xFFFF) as u16,
                 );
 
-                Ok(DNSRecord::AAAA { domain, addr, ttl })
+                DNSRecord::AAAA {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                }
+            }
+            QueryType::TXT => {
+                // One or more length-prefixed character strings
+                let mut text = String::new();
+                while buffer.pos < end {
+                    let len = buffer.read_u8()? as usize;
+                    if len > end - buffer.pos {
+                        return Err(DnsError::Malformed(
+                            "TXT string runs past the end of its record".into(),
+                        ));
+                    }
+                    let str_buf = buffer.peek_many(buffer.pos, len)?;
+                    text.push_str(&String::from_utf8_lossy(str_buf));
+                    buffer.skip(len)?;
+                }
+
+                DNSRecord::TXT {
+                    domain,
+                    class,
+                    text,
+                    ttl,
+                }
+            }
+            QueryType::SOA => {
+                let mname = buffer.read_name()?;
+                let rname = buffer.read_name()?;
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+
+                DNSRecord::SOA {
+                    domain,
+                    class,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl,
+                }
+            }
+            QueryType::PTR => {
+                let host = buffer.read_name()?;
+
+                DNSRecord::PTR {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                }
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let target = buffer.read_name()?;
+
+                DNSRecord::SRV {
+                    domain,
+                    class,
+                    priority,
+                    weight,
+                    port,
+                    target,
+                    ttl,
+                }
+            }
+            QueryType::CAA => {
+                let flags = buffer.read_u8()?;
+                let tag_len = buffer.read_u8()? as usize;
+                // Tags are case-insensitive
+                let tag =
+                    String::from_utf8_lossy(buffer.peek_many(buffer.pos, tag_len)?).to_lowercase();
+                buffer.skip(tag_len)?;
+
+                let value_len = (data_len as usize)
+                    .checked_sub(2 + tag_len)
+                    .ok_or_else(|| DnsError::Malformed("CAA tag exceeds record length".into()))?;
+                let value =
+                    String::from_utf8_lossy(buffer.peek_many(buffer.pos, value_len)?).to_string();
+                buffer.skip(value_len)?;
+
+                DNSRecord::CAA {
+                    domain,
+                    class,
+                    flags,
+                    tag,
+                    value,
+                    ttl,
+                }
+            }
+            QueryType::LOC => {
+                if data_len != 16 {
+                    return Err(DnsError::Malformed(format!(
+                        "LOC record of {} bytes instead of 16",
+                        data_len
+                    )));
+                }
+
+                DNSRecord::LOC {
+                    domain,
+                    class,
+                    version: buffer.read_u8()?,
+                    size: buffer.read_u8()?,
+                    horiz_pre: buffer.read_u8()?,
+                    vert_pre: buffer.read_u8()?,
+                    latitude: buffer.read_u32()?,
+                    longitude: buffer.read_u32()?,
+                    altitude: buffer.read_u32()?,
+                    ttl,
+                }
+            }
+            QueryType::NAPTR => {
+                let order = buffer.read_u16()?;
+                let preference = buffer.read_u16()?;
+                let flags = buffer.read_character_string()?;
+                let services = buffer.read_character_string()?;
+                let regexp = buffer.read_character_string()?;
+                let replacement = buffer.read_name()?;
+
+                DNSRecord::NAPTR {
+                    domain,
+                    class,
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                    ttl,
+                }
+            }
+            QueryType::RRSIG => {
+                let type_covered = QueryType::from_num(buffer.read_u16()?);
+                let algorithm = buffer.read_u8()?;
+                let labels = buffer.read_u8()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let signer = buffer.read_name()?;
+
+                let signature_len = end.checked_sub(buffer.pos).ok_or_else(|| {
+                    DnsError::Malformed("RRSIG signer exceeds record length".into())
+                })?;
+                let signature = buffer.peek_many(buffer.pos, signature_len)?.to_vec();
+                buffer.skip(signature_len)?;
+
+                DNSRecord::RRSIG {
+                    domain,
+                    class,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                    ttl,
+                }
+            }
+            QueryType::DNSKEY => {
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read_u8()?;
+                let algorithm = buffer.read_u8()?;
+
+                let key_len = (data_len as usize)
+                    .checked_sub(4)
+                    .ok_or_else(|| DnsError::Malformed("DNSKEY record too short".into()))?;
+                let public_key = buffer.peek_many(buffer.pos, key_len)?.to_vec();
+                buffer.skip(key_len)?;
+
+                DNSRecord::DNSKEY {
+                    domain,
+                    class,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    ttl,
+                }
+            }
+            QueryType::SVCB => {
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_name()?;
+                let params = read_options(buffer, end)?;
+
+                DNSRecord::SVCB {
+                    domain,
+                    class,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                }
+            }
+            QueryType::HTTPS => {
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_name()?;
+                let params = read_options(buffer, end)?;
+
+                DNSRecord::HTTPS {
+                    domain,
+                    class,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                }
+            }
+            QueryType::OPT => {
+                let options = read_options(buffer, end)?;
+
+                DNSRecord::OPT {
+                    udp_payload_size: class_num,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: ((ttl >> 16) & 0xFF) as u8,
+                    dnssec_ok: (ttl & 0x8000) > 0,
+                    options,
+                }
             }
-            QueryType::UNKNOWN(_) => {
+            // ANY and AXFR only ever show up in questions
+            QueryType::UNKNOWN(_) | QueryType::ANY | QueryType::AXFR => {
+                // Kept as is so the record can be passed along (RFC 3597)
+                let data = buffer.peek_many(buffer.pos, data_len as usize)?.to_vec();
                 buffer.skip(data_len as usize)?;
 
-                Ok(DNSRecord::UNKNOWN {
+                DNSRecord::UNKNOWN {
                     domain,
+                    class,
                     qtype: qtype_num,
-                    data_len,
+                    data,
                     ttl,
-                })
+                }
             }
+        };
+
+        // Data the record type doesn't account for, or data borrowed
+        // from the record after, means the message can't be trusted
+        if buffer.pos != end {
+            return Err(DnsError::Malformed(format!(
+                "{} record claims {} bytes of data but takes up {}",
+                qtype,
+                data_len,
+                buffer.pos - start
+            )));
         }
+
+        Ok(record)
     }
 
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<usize> {
+        self.check_hostnames()?;
+        self.write_raw(buffer)
+    }
+
+    pub fn write_raw(&self, buffer: &mut BytePacketBuffer) -> Result<usize> {
         let start_pos = buffer.pos;
 
         match *self {
             DNSRecord::A {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::A.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(4)?;
 
@@ -257,12 +1634,13 @@ impl DNSRecord {
             }
             DNSRecord::NS {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::NS.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos;
@@ -274,12 +1652,13 @@ impl DNSRecord {
             }
             DNSRecord::CNAME {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::CNAME.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos;
@@ -289,15 +1668,35 @@ impl DNSRecord {
                 let size = buffer.pos - (pos + 2);
                 buffer.set_u16_at(pos, size as u16)?;
             }
+            DNSRecord::DNAME {
+                ref domain,
+                class,
+                ref target,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNAME.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                // RFC 6672 forbids compressing the target
+                buffer.write_qname_uncompressed(target)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
             DNSRecord::MX {
                 ref domain,
+                class,
                 priority,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::MX.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos;
@@ -310,12 +1709,13 @@ impl DNSRecord {
             }
             DNSRecord::AAAA {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::AAAA.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(16)?;
 
@@ -323,385 +1723,2594 @@ impl DNSRecord {
                     buffer.write_u16(*octet)?;
                 }
             }
-            DNSRecord::UNKNOWN { .. } => {
-                println!("Skipping record: {:?}", self);
-            }
-        }
+            DNSRecord::TXT {
+                ref domain,
+                class,
+                ref text,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
 
-        Ok(buffer.pos - start_pos)
-    }
-}
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
-}
+                // Character strings are at most 255 bytes each
+                let bytes = text.as_bytes();
+                if bytes.is_empty() {
+                    buffer.write_u8(0)?;
+                }
+                for chunk in bytes.chunks(0xFF) {
+                    buffer.write_u8(chunk.len() as u8)?;
+                    for b in chunk {
+                        buffer.write_u8(*b)?;
+                    }
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::SOA {
+                ref domain,
+                class,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
 
-impl ResultCode {
-    pub fn from_num(num: u8) -> ResultCode {
-        match num {
-            1 => ResultCode::FORMERR,
-            2 => ResultCode::SERVFAIL,
-            3 => ResultCode::NXDOMAIN,
-            4 => ResultCode::NOTIMP,
-            5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
-        }
-    }
-}
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
 
-#[derive(Debug, Clone, Copy)]
-pub enum QueryType {
-    UNKNOWN(u16),
-    A,
-    NS,
-    CNAME,
-    MX,
-    AAAA,
-}
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::PTR {
+                ref domain,
+                class,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
 
-impl QueryType {
-    pub fn to_num(&self) -> u16 {
-        match *self {
-            QueryType::UNKNOWN(x) => x,
-            QueryType::A => 1,
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(host)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::SRV {
+                ref domain,
+                class,
+                priority,
+                weight,
+                port,
+                ref target,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                // RFC 2782 forbids compressing the target name
+                buffer.write_qname_uncompressed(target)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::CAA {
+                ref domain,
+                class,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CAA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u8(flags)?;
+                buffer.write_character_string(tag)?;
+                for b in value.as_bytes() {
+                    buffer.write_u8(*b)?;
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::RRSIG {
+                ref domain,
+                class,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(type_covered.to_num())?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(labels)?;
+                buffer.write_u32(original_ttl)?;
+                buffer.write_u32(expiration)?;
+                buffer.write_u32(inception)?;
+                buffer.write_u16(key_tag)?;
+                // RFC 4034 forbids compressing the signer's name
+                buffer.write_qname_uncompressed(signer)?;
+                for b in signature {
+                    buffer.write_u8(*b)?;
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::DNSKEY {
+                ref domain,
+                class,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(flags)?;
+                buffer.write_u8(protocol)?;
+                buffer.write_u8(algorithm)?;
+                for b in public_key {
+                    buffer.write_u8(*b)?;
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::LOC {
+                ref domain,
+                class,
+                version,
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::LOC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(16)?;
+
+                buffer.write_u8(version)?;
+                buffer.write_u8(size)?;
+                buffer.write_u8(horiz_pre)?;
+                buffer.write_u8(vert_pre)?;
+                buffer.write_u32(latitude)?;
+                buffer.write_u32(longitude)?;
+                buffer.write_u32(altitude)?;
+            }
+            DNSRecord::NAPTR {
+                ref domain,
+                class,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NAPTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(order)?;
+                buffer.write_u16(preference)?;
+                buffer.write_character_string(flags)?;
+                buffer.write_character_string(services)?;
+                buffer.write_character_string(regexp)?;
+                // RFC 3403 forbids compressing the replacement
+                buffer.write_qname_uncompressed(replacement)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::SVCB {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            }
+            | DNSRecord::HTTPS {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(self.query_type().to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                // RFC 9460 forbids compressing the target name
+                buffer.write_qname_uncompressed(target)?;
+                write_options(buffer, params)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::OPT {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                ref options,
+            } => {
+                // Root domain
+                buffer.write_u8(0)?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(udp_payload_size)?;
+                buffer.write_u32(
+                    ((extended_rcode as u32) << 24)
+                        | ((version as u32) << 16)
+                        | ((dnssec_ok as u32) << 15),
+                )?;
+
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+
+                write_options(buffer, options)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16_at(pos, size as u16)?;
+            }
+            DNSRecord::UNKNOWN {
+                ref domain,
+                class,
+                qtype,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data.len() as u16)?;
+
+                for b in data {
+                    buffer.write_u8(*b)?;
+                }
+            }
+        }
+
+        Ok(buffer.pos - start_pos)
+    }
+}
+
+/// Zone file form of the record, `<name> <ttl> <class> <type> <rdata>`
+impl fmt::Display for DNSRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unicode = f.alternate();
+        let class = match *self {
+            DNSRecord::UNKNOWN { class, .. }
+            | DNSRecord::A { class, .. }
+            | DNSRecord::NS { class, .. }
+            | DNSRecord::CNAME { class, .. }
+            | DNSRecord::DNAME { class, .. }
+            | DNSRecord::MX { class, .. }
+            | DNSRecord::AAAA { class, .. }
+            | DNSRecord::TXT { class, .. }
+            | DNSRecord::SOA { class, .. }
+            | DNSRecord::PTR { class, .. }
+            | DNSRecord::SRV { class, .. }
+            | DNSRecord::NAPTR { class, .. }
+            | DNSRecord::RRSIG { class, .. }
+            | DNSRecord::DNSKEY { class, .. }
+            | DNSRecord::LOC { class, .. }
+            | DNSRecord::SVCB { class, .. }
+            | DNSRecord::HTTPS { class, .. }
+            | DNSRecord::CAA { class, .. } => class,
+            DNSRecord::OPT {
+                udp_payload_size,
+                version,
+                dnssec_ok,
+                ref options,
+                ..
+            } => {
+                // Not a real record, shown the way dig does
+                let flags = if dnssec_ok { " do" } else { "" };
+                write!(
+                    f,
+                    "; EDNS: version: {}, flags:{}; udp: {}",
+                    version, flags, udp_payload_size
+                )?;
+                for (code, value) in options {
+                    let hex: String = value.iter().map(|b| format!("{:02x}", b)).collect();
+                    match *code {
+                        COOKIE_OPTION => write!(f, "\n; COOKIE: {}", hex)?,
+                        _ => write!(f, "\n; OPT={}: {}", code, hex)?,
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            fqdn(self.domain(), unicode),
+            self.ttl(),
+            class,
+            self.query_type()
+        )?;
+
+        match *self {
+            DNSRecord::A { addr, .. } => write!(f, "{}", addr),
+            DNSRecord::AAAA { addr, .. } => write!(f, "{}", addr),
+            DNSRecord::NS { ref host, .. }
+            | DNSRecord::CNAME { ref host, .. }
+            | DNSRecord::PTR { ref host, .. } => write!(f, "{}", fqdn(host, unicode)),
+            DNSRecord::DNAME { ref target, .. } => write!(f, "{}", fqdn(target, unicode)),
+            DNSRecord::MX {
+                priority, ref host, ..
+            } => write!(f, "{} {}", priority, fqdn(host, unicode)),
+            DNSRecord::TXT { ref text, .. } => write!(f, "{}", quoted(text.as_bytes())),
+            DNSRecord::SOA {
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                fqdn(mname, unicode),
+                fqdn(rname, unicode),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DNSRecord::SRV {
+                priority,
+                weight,
+                port,
+                ref target,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {}",
+                priority,
+                weight,
+                port,
+                fqdn(target, unicode)
+            ),
+            DNSRecord::CAA {
+                flags,
+                ref tag,
+                ref value,
+                ..
+            } => write!(f, "{} {} {}", flags, tag, quoted(value.as_bytes())),
+            DNSRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                fqdn(signer, unicode),
+                base64(signature)
+            ),
+            DNSRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {}",
+                flags,
+                protocol,
+                algorithm,
+                base64(public_key)
+            ),
+            DNSRecord::LOC {
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ..
+            } => {
+                // Altitude is kept in centimeters above -100km
+                let altitude = altitude as i64 - 10_000_000;
+                let sign = if altitude < 0 { "-" } else { "" };
+                write!(
+                    f,
+                    "{} {} {}{}.{:02}m {} {} {}",
+                    loc_coordinate(latitude, 'N', 'S'),
+                    loc_coordinate(longitude, 'E', 'W'),
+                    sign,
+                    altitude.abs() / 100,
+                    altitude.abs() % 100,
+                    loc_size(size),
+                    loc_size(horiz_pre),
+                    loc_size(vert_pre)
+                )
+            }
+            DNSRecord::NAPTR {
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                quoted(flags.as_bytes()),
+                quoted(services.as_bytes()),
+                quoted(regexp.as_bytes()),
+                fqdn(replacement, unicode)
+            ),
+            DNSRecord::SVCB {
+                priority,
+                ref target,
+                ref params,
+                ..
+            }
+            | DNSRecord::HTTPS {
+                priority,
+                ref target,
+                ref params,
+                ..
+            } => {
+                write!(f, "{} {}", priority, fqdn(target, unicode))?;
+                for (key, value) in params {
+                    write!(f, " {}", svc_param(*key, value))?;
+                }
+                Ok(())
+            }
+            // RFC 3597 generic form
+            DNSRecord::UNKNOWN { ref data, .. } => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    for b in data {
+                        write!(f, "{:02x}", b)?;
+                    }
+                }
+                Ok(())
+            }
+            DNSRecord::OPT { .. } => Ok(()),
+        }
+    }
+}
+
+/// Standard base64 with padding, how keys and signatures are shown
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// LOC latitude or longitude as degrees, minutes and seconds,
+/// e.g. `51 30 12.748 N`
+fn loc_coordinate(value: u32, positive: char, negative: char) -> String {
+    let offset = value as i64 - (1 << 31);
+    let hemisphere = if offset < 0 { negative } else { positive };
+    let ms = offset.abs();
+    format!(
+        "{} {} {}.{:03} {}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000,
+        hemisphere
+    )
+}
+
+/// LOC size or precision, stored as a mantissa and a power of ten
+/// exponent of centimeters, in meters
+fn loc_size(value: u8) -> String {
+    let cm = (value >> 4) as u64 * 10u64.pow((value & 0x0F).min(9) as u32);
+    if cm >= 100 {
+        format!("{}m", cm / 100)
+    } else {
+        format!("0.{:02}m", cm)
+    }
+}
+
+/// Presentation form of a SvcParam from RFC 9460, keys this doesn't
+/// know the value format of are shown as `key<N>` with a quoted value
+fn svc_param(key: u16, value: &[u8]) -> String {
+    match key {
+        // Lists of length-prefixed protocol ids
+        1 => {
+            let mut ids = Vec::new();
+            let mut rest = value;
+            while let Some((&len, tail)) = rest.split_first() {
+                let len = (len as usize).min(tail.len());
+                ids.push(String::from_utf8_lossy(&tail[..len]).into_owned());
+                rest = &tail[len..];
+            }
+            format!("alpn={}", ids.join(","))
+        }
+        2 if value.is_empty() => "no-default-alpn".to_string(),
+        3 if value.len() == 2 => format!("port={}", u16::from_be_bytes([value[0], value[1]])),
+        4 if value.len().is_multiple_of(4) => {
+            let addrs: Vec<String> = value
+                .chunks(4)
+                .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).to_string())
+                .collect();
+            format!("ipv4hint={}", addrs.join(","))
+        }
+        6 if value.len().is_multiple_of(16) => {
+            let addrs: Vec<String> = value
+                .chunks(16)
+                .map(|octets| {
+                    let mut addr = [0; 16];
+                    addr.copy_from_slice(octets);
+                    Ipv6Addr::from(addr).to_string()
+                })
+                .collect();
+            format!("ipv6hint={}", addrs.join(","))
+        }
+        _ => format!("key{}={}", key, quoted(value)),
+    }
+}
+
+/// Host names are checked in the form they go on the wire in
+fn check_hostname(name: &str) -> Result<()> {
+    if name::is_hostname(&name::to_ascii(name)) {
+        Ok(())
+    } else {
+        Err(DnsError::InvalidHostname(name.to_string()))
+    }
+}
+
+/// Key, length and value triples running up to `end`, the layout of
+/// both EDNS options and the SvcParams of SVCB and HTTPS records
+fn read_options(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<(u16, Vec<u8>)>> {
+    let mut options = Vec::new();
+
+    while buffer.pos < end {
+        let key = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        if buffer.pos + len > end {
+            return Err(DnsError::Malformed(format!(
+                "Option {} exceeds record length",
+                key
+            )));
+        }
+        options.push((key, buffer.peek_many(buffer.pos, len)?.to_vec()));
+        buffer.skip(len)?;
+    }
+
+    Ok(options)
+}
+
+fn write_options(buffer: &mut BytePacketBuffer, options: &[(u16, Vec<u8>)]) -> Result<()> {
+    for (key, value) in options {
+        buffer.write_u16(*key)?;
+        buffer.write_u16(value.len() as u16)?;
+        for b in value {
+            buffer.write_u8(*b)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResultCode {
+    /// Unassigned or not implemented here, keeps the code
+    /// so it isn't mistaken for success
+    UNKNOWN(u16),
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    /// Codes from here on need the upper bits from the OPT record
+    BADVERS,
+    BADCOOKIE,
+}
+
+impl ResultCode {
+    /// Full 12-bit code, the header holds the lower 4 bits
+    /// and the OPT record the upper 8
+    pub fn to_num(self) -> u16 {
+        match self {
+            ResultCode::UNKNOWN(x) => x,
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::BADVERS => 16,
+            ResultCode::BADCOOKIE => 23,
+        }
+    }
+
+    /// Mnemonic from the IANA registry, as `dig` shows in its status
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ResultCode::UNKNOWN(_) => "UNKNOWN",
+            ResultCode::NOERROR => "NOERROR",
+            ResultCode::FORMERR => "FORMERR",
+            ResultCode::SERVFAIL => "SERVFAIL",
+            ResultCode::NXDOMAIN => "NXDOMAIN",
+            ResultCode::NOTIMP => "NOTIMP",
+            ResultCode::REFUSED => "REFUSED",
+            ResultCode::YXDOMAIN => "YXDOMAIN",
+            ResultCode::YXRRSET => "YXRRSET",
+            ResultCode::NXRRSET => "NXRRSET",
+            ResultCode::NOTAUTH => "NOTAUTH",
+            ResultCode::NOTZONE => "NOTZONE",
+            ResultCode::BADVERS => "BADVERS",
+            ResultCode::BADCOOKIE => "BADCOOKIE",
+        }
+    }
+
+    /// What the code means, in the words of the IANA registry
+    pub fn description(self) -> &'static str {
+        match self {
+            ResultCode::UNKNOWN(_) => "Unassigned",
+            ResultCode::NOERROR => "No Error",
+            ResultCode::FORMERR => "Format Error",
+            ResultCode::SERVFAIL => "Server Failure",
+            ResultCode::NXDOMAIN => "Non-Existent Domain",
+            ResultCode::NOTIMP => "Not Implemented",
+            ResultCode::REFUSED => "Query Refused",
+            ResultCode::YXDOMAIN => "Name Exists when it should not",
+            ResultCode::YXRRSET => "RR Set Exists when it should not",
+            ResultCode::NXRRSET => "RR Set that should exist does not",
+            ResultCode::NOTAUTH => "Server Not Authoritative for zone",
+            ResultCode::NOTZONE => "Name not contained in zone",
+            ResultCode::BADVERS => "Bad OPT Version",
+            ResultCode::BADCOOKIE => "Bad/missing Server Cookie",
+        }
+    }
+
+    pub fn from_num(num: u16) -> ResultCode {
+        match num {
+            0 => ResultCode::NOERROR,
+            1 => ResultCode::FORMERR,
+            2 => ResultCode::SERVFAIL,
+            3 => ResultCode::NXDOMAIN,
+            4 => ResultCode::NOTIMP,
+            5 => ResultCode::REFUSED,
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            16 => ResultCode::BADVERS,
+            23 => ResultCode::BADCOOKIE,
+            _ => ResultCode::UNKNOWN(num),
+        }
+    }
+}
+
+/// Mnemonic and code, e.g. `NXDOMAIN (3)`. The alternate form `{:#}`
+/// adds the description: `NXDOMAIN (3): Non-Existent Domain`.
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.to_str(), self.to_num())?;
+        if f.alternate() {
+            write!(f, ": {}", self.description())?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of request, nearly always a standard QUERY
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpCode {
+    UNKNOWN(u8),
+    QUERY,
+    /// Inverse query, obsoleted by RFC 3425
+    IQUERY,
+    STATUS,
+    /// Zone change announcement (RFC 1996)
+    NOTIFY,
+    /// Dynamic update (RFC 2136)
+    UPDATE,
+}
+
+impl OpCode {
+    pub fn to_num(self) -> u8 {
+        match self {
+            OpCode::UNKNOWN(x) => x,
+            OpCode::QUERY => 0,
+            OpCode::IQUERY => 1,
+            OpCode::STATUS => 2,
+            OpCode::NOTIFY => 4,
+            OpCode::UPDATE => 5,
+        }
+    }
+
+    pub fn from_num(num: u8) -> OpCode {
+        match num {
+            0 => OpCode::QUERY,
+            1 => OpCode::IQUERY,
+            2 => OpCode::STATUS,
+            4 => OpCode::NOTIFY,
+            5 => OpCode::UPDATE,
+            _ => OpCode::UNKNOWN(num),
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCode::UNKNOWN(x) => write!(f, "{}", x),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryType {
+    UNKNOWN(u16),
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    /// Geographic location (RFC 1876)
+    LOC,
+    NAPTR,
+    /// Alias for a whole subtree (RFC 6672)
+    DNAME,
+    OPT,
+    RRSIG,
+    DNSKEY,
+    /// Request for a whole zone, over TCP (RFC 5936)
+    AXFR,
+    /// Request for every record type at a name
+    ANY,
+    CAA,
+    /// Service binding (RFC 9460)
+    SVCB,
+    HTTPS,
+}
+
+impl QueryType {
+    pub fn to_num(self) -> u16 {
+        match self {
+            QueryType::UNKNOWN(x) => x,
+            QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::LOC => 29,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::RRSIG => 46,
+            QueryType::DNSKEY => 48,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::CAA => 257,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryType {
+        match num {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            28 => QueryType::AAAA,
+            29 => QueryType::LOC,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            46 => QueryType::RRSIG,
+            48 => QueryType::DNSKEY,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
+            257 => QueryType::CAA,
+            _ => QueryType::UNKNOWN(num),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryClass {
+    UNKNOWN(u16),
+    /// The internet, what nearly every query is for
+    IN,
+    /// Chaos, used for server info such as `version.bind`
+    CH,
+    HS,
+    /// Request for every class
+    ANY,
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            _ => write!(f, "{:?}", self),
         }
     }
+}
+
+/// Type by name as shown by `Display`, case-insensitively,
+/// e.g. `mx` or `TYPE99` for one without a name
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<QueryType> {
+        let s = s.to_uppercase();
+        let qtype = match s.as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "PTR" => QueryType::PTR,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "AAAA" => QueryType::AAAA,
+            "SRV" => QueryType::SRV,
+            "LOC" => QueryType::LOC,
+            "NAPTR" => QueryType::NAPTR,
+            "DNAME" => QueryType::DNAME,
+            "OPT" => QueryType::OPT,
+            "RRSIG" => QueryType::RRSIG,
+            "DNSKEY" => QueryType::DNSKEY,
+            "SVCB" => QueryType::SVCB,
+            "HTTPS" => QueryType::HTTPS,
+            "AXFR" => QueryType::AXFR,
+            "ANY" => QueryType::ANY,
+            "CAA" => QueryType::CAA,
+            _ => s
+                .strip_prefix("TYPE")
+                .and_then(|num| num.parse().ok())
+                .map(QueryType::from_num)
+                .ok_or_else(|| DnsError::Config(format!("Unknown record type {}", s)))?,
+        };
+        Ok(qtype)
+    }
+}
+
+impl QueryClass {
+    pub fn to_num(self) -> u16 {
+        match self {
+            QueryClass::UNKNOWN(x) => x,
+            QueryClass::IN => 1,
+            QueryClass::CH => 3,
+            QueryClass::HS => 4,
+            QueryClass::ANY => 255,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryClass {
+        match num {
+            1 => QueryClass::IN,
+            3 => QueryClass::CH,
+            4 => QueryClass::HS,
+            255 => QueryClass::ANY,
+            _ => QueryClass::UNKNOWN(num),
+        }
+    }
+}
+
+impl fmt::Display for QueryClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryClass::UNKNOWN(x) => write!(f, "CLASS{}", x),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSQuestion {
+    pub name: String,
+    pub qtype: QueryType,
+    pub class: QueryClass,
+}
+
+impl DNSQuestion {
+    /// Question in the IN class
+    pub fn new(name: String, qtype: QueryType) -> Self {
+        DNSQuestion {
+            name,
+            qtype,
+            class: QueryClass::IN,
+        }
+    }
+
+    /// Whether `other` asks the same thing, as a response has to echo
+    /// the question. Names are compared case-insensitively and types and
+    /// classes by number, so `TYPE35` is NAPTR. ANY is only matched by
+    /// ANY, it stands for every type in what's asked, not in the echo.
+    pub fn matches(&self, other: &DNSQuestion) -> bool {
+        name::normalize(&self.name) == name::normalize(&other.name)
+            && self.qtype.to_num() == other.qtype.to_num()
+            && self.class.to_num() == other.class.to_num()
+    }
+
+    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.name = buffer.read_name()?;
+        self.qtype = QueryType::from_num(buffer.read_u16()?);
+        self.class = QueryClass::from_num(buffer.read_u16()?);
+        Ok(())
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_qname(&self.name)?;
+        let qtype = self.qtype.to_num();
+        buffer.write_u16(qtype)?;
+        buffer.write_u16(self.class.to_num())?;
+
+        Ok(())
+    }
+}
+
+/// Commented out the way dig shows questions
+impl fmt::Display for DNSQuestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unicode = f.alternate();
+        write!(
+            f,
+            ";{}\t\t{}\t{}",
+            fqdn(&self.name, unicode),
+            self.class,
+            self.qtype
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSHeader {
+    pub id: u16,
+    pub recursion_desired: bool,
+    pub truncated_message: bool,
+    pub authoritative_answer: bool,
+    pub opcode: OpCode,
+    pub response: bool,
+    pub rescode: ResultCode,
+    pub checking_disabled: bool,
+    pub authed_data: bool,
+    pub z: bool,
+    pub recursion_available: bool,
+    pub questions: u16,
+    pub answers: u16,
+    pub authoritative_entries: u16,
+    pub resource_entries: u16,
+}
+
+impl Default for DNSHeader {
+    fn default() -> Self {
+        DNSHeader::new()
+    }
+}
+
+impl DNSHeader {
+    pub fn new() -> DNSHeader {
+        DNSHeader {
+            id: 0,
+            recursion_desired: false,
+            truncated_message: false,
+            authoritative_answer: false,
+            opcode: OpCode::QUERY,
+            response: false,
+            rescode: ResultCode::NOERROR,
+            checking_disabled: false,
+            authed_data: false,
+            z: false,
+            recursion_available: false,
+            questions: 0,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        }
+    }
+
+    /// The 16 bits between the id and the counts, as they go on the wire
+    pub fn flags_word(&self) -> u16 {
+        let a = (self.recursion_desired as u8)
+            | ((self.truncated_message as u8) << 1)
+            | ((self.authoritative_answer as u8) << 2)
+            | ((self.opcode.to_num() & 0x0F) << 3)
+            | ((self.response as u8) << 7);
+
+        let b = ((self.rescode.to_num() & 0x0F) as u8)
+            | ((self.checking_disabled as u8) << 4)
+            | ((self.authed_data as u8) << 5)
+            | ((self.z as u8) << 6)
+            | ((self.recursion_available as u8) << 7);
+
+        u16::from_be_bytes([a, b])
+    }
+
+    /// Sets every flag, opcode and rcode from the bits as they go on the
+    /// wire. Only the low 4 bits of the rcode fit, an extended one lives
+    /// in the OPT record.
+    pub fn set_flags_word(&mut self, flags: u16) {
+        let [a, b] = flags.to_be_bytes();
+
+        self.recursion_desired = (a & (1 << 0)) > 0;
+        self.truncated_message = (a & (1 << 1)) > 0;
+        self.authoritative_answer = (a & (1 << 2)) > 0;
+        self.opcode = OpCode::from_num((a >> 3) & 0x0F);
+        self.response = (a & (1 << 7)) > 0;
+
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
+        self.checking_disabled = (b & (1 << 4)) > 0;
+        self.authed_data = (b & (1 << 5)) > 0;
+        self.z = (b & (1 << 6)) > 0;
+        self.recursion_available = (b & (1 << 7)) > 0;
+    }
+
+    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.id = buffer.read_u16()?;
+        self.set_flags_word(buffer.read_u16()?);
+
+        self.questions = buffer.read_u16()?;
+        self.answers = buffer.read_u16()?;
+        self.authoritative_entries = buffer.read_u16()?;
+        self.resource_entries = buffer.read_u16()?;
+
+        Ok(())
+    }
+}
+
+impl DNSHeader {
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_u16(self.id)?;
+        buffer.write_u16(self.flags_word())?;
+
+        buffer.write_u16(self.questions)?;
+        buffer.write_u16(self.answers)?;
+        buffer.write_u16(self.authoritative_entries)?;
+        buffer.write_u16(self.resource_entries)?;
+
+        Ok(())
+    }
+}
+
+pub struct BytePacketBuffer {
+    pub buf: Vec<u8>,
+    pub pos: usize,
+    /// How much of `buf` holds the message, reads stop here
+    len: usize,
+    /// Offsets of the domain suffixes written so far,
+    /// used for name compression
+    names: HashMap<String, usize>,
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        BytePacketBuffer::new()
+    }
+}
+
+impl BytePacketBuffer {
+    /// Buffer sized for a plain UDP message
+    pub fn new() -> Self {
+        BytePacketBuffer::with_size(512)
+    }
+
+    /// Buffer of arbitrary size, e.g. the up to 64KB
+    /// a message sent over TCP can carry
+    pub fn with_size(size: usize) -> Self {
+        BytePacketBuffer {
+            buf: vec![0; size],
+            pos: 0,
+            len: 0,
+            names: HashMap::new(),
+        }
+    }
+
+    /// The bytes of the message, as received or written so far
+    pub fn message(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Marks the first `len` bytes of `buf` as the message after
+    /// filling it directly, e.g. with what a socket received
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.buf.len());
+    }
+
+    /// Reads a possibly compressed name at the current position
+    pub fn read_name(&mut self) -> Result<String> {
+        let mut name = String::new();
+        self.read_qname(&mut name)?;
+        Ok(name)
+    }
+
+    fn read_qname(&mut self, out: &mut String) -> Result<()> {
+        let mut pos = self.pos;
+        let mut jumped = false;
+        // Prevent cycles
+        let max_jumps = 5;
+        let mut curr_jump = 0;
+
+        let mut delimiter = "";
+
+        loop {
+            if curr_jump > max_jumps {
+                return Err(DnsError::JumpLimitExceeded {
+                    offset: pos,
+                    jumps: curr_jump,
+                });
+            }
+
+            // Get length of label
+            let len = self.peek(pos)?;
+
+            // Most significant two bits set
+            // Jump to another offset
+            if (len & 0xC0) == 0xC0 {
+                // Only the first pointer moves the position, to just past
+                // itself. `pos` is there even after labels came first, as
+                // in `www` followed by a pointer to `example.com`, and
+                // whatever the pointer leads to lies elsewhere.
+                if !jumped {
+                    self.set_position(pos + 2)?;
+                }
+
+                let b2 = self.peek(pos + 1)? as u16;
+                let offset = (((len as u16) ^ 0xC0) << 8 | b2) as usize;
+
+                // Pointers must go backwards, anything else is a loop
+                // or garbage
+                if offset >= pos {
+                    return Err(DnsError::InvalidPointer {
+                        offset: pos,
+                        target: offset,
+                    });
+                }
+                pos = offset;
+
+                jumped = true;
+                curr_jump += 1;
+                continue;
+            } else {
+                // Read label
+
+                // Skip the length
+                pos += 1;
+
+                // End of domain name
+                if len == 0 {
+                    break;
+                }
+
+                out.push_str(delimiter);
+                let str_buf = self.peek_many(pos, len as usize)?;
+                // Case is kept as sent, names are compared case-insensitively
+                out.push_str(&String::from_utf8_lossy(str_buf));
+                delimiter = ".";
+                pos += len as usize;
+            }
+        }
+
+        if !jumped {
+            self.set_position(pos)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a length-prefixed character string
+    fn read_character_string(&mut self) -> Result<String> {
+        let len = self.read_u8()? as usize;
+        let text = String::from_utf8_lossy(self.peek_many(self.pos, len)?).into_owned();
+        self.skip(len)?;
+        Ok(text)
+    }
+
+    fn skip(&mut self, amount: usize) -> Result<()> {
+        let pos = self.pos.checked_add(amount).ok_or(DnsError::EndOfBuffer)?;
+        self.set_position(pos)
+    }
+
+    /// Moves to `pos`, which may be just past the end of the
+    /// message but no further
+    fn set_position(&mut self, pos: usize) -> Result<()> {
+        if pos > self.len {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.pos = pos;
+
+        Ok(())
+    }
+
+    /// Cuts the message off at `pos`, dropping whatever was written
+    /// past it along with the names there that could be pointed to
+    fn truncate_to(&mut self, pos: usize) -> Result<()> {
+        self.set_position(pos)?;
+        self.len = pos;
+        self.names.retain(|_, offset| *offset < pos);
+
+        Ok(())
+    }
+
+    /// Read a single byte and increment the position by one
+    fn read_u8(&mut self) -> Result<u8> {
+        if self.end_of_buf() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        let res = self.buf[self.pos];
+        self.pos += 1;
+        Ok(res)
+    }
+
+    /// Read two bytes and increment the position by two
+    fn read_u16(&mut self) -> Result<u16> {
+        let res = ((self.read_u8()? as u16) << 8) | (self.read_u8()? as u16);
+        Ok(res)
+    }
+
+    /// Read four bytes and increment the position by four
+    fn read_u32(&mut self) -> Result<u32> {
+        let res = ((self.read_u8()? as u32) << 24)
+            | ((self.read_u8()? as u32) << 16)
+            | ((self.read_u8()? as u32) << 8)
+            | (self.read_u8()? as u32);
+        Ok(res)
+    }
+
+    fn peek(&self, pos: usize) -> Result<u8> {
+        if pos >= self.len {
+            return Err(DnsError::EndOfBuffer);
+        }
+        Ok(self.buf[pos])
+    }
+
+    pub fn peek_many(&self, start: usize, len: usize) -> Result<&[u8]> {
+        let end = start.checked_add(len).ok_or(DnsError::EndOfBuffer)?;
+        if end > self.len {
+            return Err(DnsError::EndOfBuffer);
+        }
+        Ok(&self.buf[start..end])
+    }
+
+    /// Whether reading has reached the end of the message
+    fn end_of_buf(&self) -> bool {
+        self.pos >= self.len
+    }
+
+    /// Offset, hex and ASCII dump of `len` bytes from `start`, 16 to a
+    /// line. Whatever lies past the end of the message is left out.
+    pub fn hexdump(&self, start: usize, len: usize) -> String {
+        let end = start.saturating_add(len).min(self.len);
+        let bytes = self.buf.get(start..end).unwrap_or_default();
+
+        let mut out = String::new();
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+
+            if i > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!(
+                "{:04x}  {:<47}  |{}|",
+                start + i * 16,
+                hex.join(" "),
+                ascii
+            ));
+        }
+        out
+    }
+}
+
+impl BytePacketBuffer {
+    fn set_u8_at(&mut self, pos: usize, value: u8) -> Result<()> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.buf[pos] = value;
+
+        Ok(())
+    }
+
+    fn set_u16_at(&mut self, pos: usize, value: u16) -> Result<()> {
+        // Both bytes are checked before either is written
+        if pos.checked_add(1).is_none_or(|last| last >= self.buf.len()) {
+            return Err(DnsError::EndOfBuffer);
+        }
+        self.set_u8_at(pos, (value >> 8) as u8)?;
+        self.set_u8_at(pos + 1, (value & 0xFF) as u8)?;
+
+        Ok(())
+    }
+
+    /// Write a single byte to the buffer and increment
+    /// the position by one.
+    fn write(&mut self, value: u8) -> Result<()> {
+        if self.pos >= self.buf.len() {
+            return Err(DnsError::EndOfBuffer);
+        }
+
+        self.buf[self.pos] = value;
+        self.pos += 1;
+        // What was written can be read back
+        self.len = self.len.max(self.pos);
+        Ok(())
+    }
+
+    fn write_u8(&mut self, value: u8) -> Result<()> {
+        self.write(value)?;
+        Ok(())
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<()> {
+        self.write((value >> 8) as u8)?;
+        self.write((value & 0xFF) as u8)?;
+        Ok(())
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<()> {
+        self.write(((value >> 24) & 0xFF) as u8)?;
+        self.write(((value >> 16) & 0xFF) as u8)?;
+        self.write(((value >> 8) & 0xFF) as u8)?;
+        self.write(((value >> 0) & 0xFF) as u8)?;
+        Ok(())
+    }
+
+    /// Writes a length-prefixed character string, at most 255 bytes
+    fn write_character_string(&mut self, text: &str) -> Result<()> {
+        let len = u8::try_from(text.len()).map_err(|_| {
+            DnsError::Malformed(format!("{} bytes is too long for a string", text.len()))
+        })?;
+        self.write_u8(len)?;
+        for b in text.as_bytes() {
+            self.write_u8(*b)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a name as is, any octets other than dots are allowed
+    /// in its labels, e.g. `_dmarc.example.com`
+    fn write_qname(&mut self, qname: &str) -> Result<()> {
+        self.write_labels(qname, true)
+    }
+
+    /// For names that other resolvers expect in full, e.g. the SRV target
+    fn write_qname_uncompressed(&mut self, qname: &str) -> Result<()> {
+        self.write_labels(qname, false)
+    }
+
+    fn write_labels(&mut self, qname: &str, compress: bool) -> Result<()> {
+        // Unicode labels go on the wire as `xn--` A-labels
+        let qname = name::to_ascii(qname);
+        // A single trailing dot stands for the root, which is written
+        // at the end anyway, so `example.com.` equals `example.com`
+        let qname = qname.strip_suffix('.').unwrap_or(&qname);
+        let labels: Vec<&str> = if qname.is_empty() {
+            Vec::new()
+        } else {
+            qname.split('.').collect()
+        };
+
+        // Checked up front so an invalid name isn't partially written,
+        // nor left behind in `names` for later names to point to
+
+        // An empty label would end the name early
+        if labels.iter().any(|label| label.is_empty()) {
+            return Err(DnsError::EmptyLabel);
+        }
+        if labels.iter().any(|label| label.len() > 0x3f) {
+            return Err(DnsError::LabelTooLong);
+        }
+        if name::encoded_len(qname) > 255 {
+            return Err(DnsError::NameTooLong);
+        }
+
+        for (i, label) in labels.iter().enumerate() {
+            let suffix = labels[i..].join(".").to_lowercase();
+
+            // Suffix already in the packet, point back to it
+            if compress {
+                if let Some(&offset) = self.names.get(&suffix) {
+                    self.write_u16(0xC000 | offset as u16)?;
+                    return Ok(());
+                }
+            }
+
+            // Pointers only have 14 bits for the offset
+            if self.pos < 0x4000 {
+                self.names.entry(suffix).or_insert(self.pos);
+            }
+
+            self.write_u8(label.len() as u8)?;
+            for b in label.as_bytes() {
+                self.write_u8(*b)?;
+            }
+        }
+
+        self.write_u8(0)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn round_trip(packet: &mut DNSPacket) -> DNSPacket {
+        let bytes = packet.to_bytes().unwrap();
+        DNSPacket::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn soa_round_trips() {
+        let soa = DNSRecord::SOA {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            mname: "ns1.example.com".into(),
+            rname: "hostmaster.example.com".into(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        };
+
+        let mut packet = DNSPacket::query("example.com", QueryType::SOA);
+        packet.answers.push(soa.clone());
+
+        assert_eq!(round_trip(&mut packet).answers, vec![soa]);
+    }
+
+    #[test]
+    fn query_ids_are_random() {
+        let ids: HashSet<u16> = (0..16)
+            .map(|_| DNSPacket::query("example.com", QueryType::A).header.id)
+            .collect();
+
+        // A fixed id, or one that barely changes, would be easy to spoof
+        assert!(ids.len() > 8);
+    }
+
+    /// Buffer holding `bytes` as a received message
+    fn buffer_of(bytes: &[u8]) -> BytePacketBuffer {
+        let mut buffer = BytePacketBuffer::with_size(bytes.len());
+        buffer.buf.copy_from_slice(bytes);
+        buffer.set_len(bytes.len());
+        buffer
+    }
+
+    #[test]
+    fn peek_many_reads_up_to_the_end() {
+        let buffer = buffer_of(&[1, 2, 3, 4]);
+
+        assert_eq!(buffer.peek_many(0, 4).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(buffer.peek_many(2, 2).unwrap(), [3, 4]);
+        assert!(buffer.peek_many(4, 0).unwrap().is_empty());
+        assert!(matches!(buffer.peek_many(1, 4), Err(DnsError::EndOfBuffer)));
+        assert!(matches!(
+            buffer.peek_many(usize::MAX, 2),
+            Err(DnsError::EndOfBuffer)
+        ));
+    }
+
+    #[test]
+    fn set_u8_at_stays_within_the_buffer() {
+        let mut buffer = BytePacketBuffer::with_size(2);
+
+        buffer.set_u8_at(1, 0xFF).unwrap();
+        assert!(matches!(
+            buffer.set_u8_at(2, 0xFF),
+            Err(DnsError::EndOfBuffer)
+        ));
+        // Neither byte is written when the second doesn't fit
+        assert!(matches!(
+            buffer.set_u16_at(1, 0xABCD),
+            Err(DnsError::EndOfBuffer)
+        ));
+        assert_eq!(buffer.buf, [0, 0xFF]);
+    }
+
+    fn a_record(domain: &str, addr: [u8; 4]) -> DNSRecord {
+        DNSRecord::A {
+            domain: domain.into(),
+            class: QueryClass::IN,
+            addr: Ipv4Addr::from(addr),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn repeated_names_are_compressed() {
+        let mut packet = DNSPacket::query("www.example.com", QueryType::A);
+        packet
+            .answers
+            .push(a_record("www.example.com", [192, 0, 2, 1]));
+        packet
+            .answers
+            .push(a_record("mail.Example.com", [192, 0, 2, 2]));
+
+        let bytes = packet.to_bytes().unwrap();
+
+        // The question's name starts right after the header, at 12
+        let question_end = 12 + 17 + 4;
+        // The first owner is the whole name over again
+        assert_eq!(bytes[question_end..question_end + 2], [0xC0, 12]);
+        // The second shares example.com, whatever its case
+        let second = question_end + 2 + 14;
+        assert_eq!(
+            bytes[second..second + 7],
+            [4, b'm', b'a', b'i', b'l', 0xC0, 16]
+        );
+        assert_eq!(bytes.len(), second + 7 + 14);
+
+        // Pointing back takes on the case written first
+        let answers = DNSPacket::from_bytes(&bytes).unwrap().answers;
+        assert_eq!(answers[1].domain(), "mail.example.com");
+    }
+
+    #[test]
+    fn srv_targets_are_not_compressed() {
+        let srv = DNSRecord::SRV {
+            domain: "_sip._tcp.example.com".into(),
+            class: QueryClass::IN,
+            priority: 10,
+            weight: 5,
+            port: 5060,
+            target: "example.com".into(),
+            ttl: 300,
+        };
+        let mut packet = DNSPacket::query("_sip._tcp.example.com", QueryType::SRV);
+        packet.answers.push(srv.clone());
+
+        let bytes = packet.to_bytes().unwrap();
+
+        assert!(bytes.ends_with(b"\x07example\x03com\x00"));
+        assert_eq!(DNSPacket::from_bytes(&bytes).unwrap().answers, vec![srv]);
+    }
+
+    fn txt_record(domain: &str, text: &str) -> DNSRecord {
+        DNSRecord::TXT {
+            domain: domain.into(),
+            class: QueryClass::IN,
+            text: text.into(),
+            ttl: 300,
+        }
+    }
+
+    /// Response with `count` TXT records of 50 characters each
+    fn txt_response(count: usize) -> DNSPacket {
+        let mut packet = DNSPacket::query("example.com", QueryType::TXT);
+        packet.header.response = true;
+        for i in 0..count {
+            packet
+                .answers
+                .push(txt_record("example.com", &format!("{:050}", i)));
+        }
+        packet.resources.push(DNSRecord::opt(1232));
+        packet
+    }
+
+    #[test]
+    fn responses_that_fit_are_not_truncated() {
+        let mut packet = txt_response(3);
+        let mut buffer = BytePacketBuffer::new();
+        packet.write_truncated(&mut buffer).unwrap();
+
+        let response = DNSPacket::from_bytes(buffer.message()).unwrap();
+        assert!(!response.header.truncated_message);
+        assert_eq!(response.answers.len(), 3);
+    }
+
+    #[test]
+    fn oversized_responses_are_truncated() {
+        let mut packet = txt_response(20);
+        let mut buffer = BytePacketBuffer::new();
+        packet.write_truncated(&mut buffer).unwrap();
+
+        // Nothing of the record that didn't fit is left behind
+        assert_eq!(buffer.message().len(), buffer.pos);
+        let response = DNSPacket::from_bytes(buffer.message()).unwrap();
+        assert!(response.header.truncated_message);
+        assert!(response.answers.len() < 20);
+        assert_eq!(response.answers, packet.answers);
+    }
+
+    #[test]
+    fn truncation_keeps_the_opt_record() {
+        for count in 0..20 {
+            let mut packet = txt_response(count);
+            packet.header.rescode = ResultCode::BADCOOKIE;
+            let mut buffer = BytePacketBuffer::new();
+            packet.write_truncated(&mut buffer).unwrap();
+
+            // The upper rcode bits only make it through in the OPT record
+            let response = DNSPacket::from_bytes(buffer.message()).unwrap();
+            assert_eq!(response.header.rescode, ResultCode::BADCOOKIE);
+            assert_eq!(response.max_payload_size(), 1232);
+        }
+    }
+
+    fn ns_record(domain: &str, host: &str) -> DNSRecord {
+        DNSRecord::NS {
+            domain: domain.into(),
+            class: QueryClass::IN,
+            host: host.into(),
+            ttl: 3600,
+        }
+    }
+
+    /// Referral to the servers of example.com, only one of which
+    /// comes with glue
+    fn referral() -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.response = true;
+        packet
+            .authorities
+            .push(ns_record("example.com", "ns1.example.com"));
+        packet
+            .authorities
+            .push(ns_record("example.com", "ns.example.net"));
+        packet
+            .resources
+            .push(a_record("NS1.example.com", [192, 0, 2, 53]));
+        packet
+    }
+
+    #[test]
+    fn referrals_give_glue_and_server_names() {
+        let packet = referral();
+        let qname = "www.example.com";
+
+        assert!(packet.is_referral(qname));
+        assert_eq!(
+            packet.get_resolved_ns(qname),
+            Some(IpAddr::from([192, 0, 2, 53]))
+        );
+        assert_eq!(packet.get_unresolved_ns(qname), Some("ns1.example.com"));
+        assert_eq!(
+            packet.get_all_unresolved_ns(qname).collect::<Vec<_>>(),
+            ["ns1.example.com", "ns.example.net"]
+        );
+    }
+
+    #[test]
+    fn referrals_elsewhere_are_ignored() {
+        let packet = referral();
+
+        assert!(!packet.is_referral("www.example.org"));
+        assert_eq!(packet.get_resolved_ns("www.example.org"), None);
+        assert_eq!(packet.get_unresolved_ns("www.example.org"), None);
+    }
+
+    #[test]
+    fn get_random_a_picks_one_of_the_answers() {
+        let mut packet = DNSPacket::new();
+        assert_eq!(packet.get_random_a(), None);
+
+        packet.answers.push(a_record("example.com", [192, 0, 2, 1]));
+        packet.answers.push(a_record("example.com", [192, 0, 2, 2]));
+        let addr = packet.get_random_a().unwrap();
+        assert!(packet.get_all_a().any(|a| a == addr));
+    }
+
+    #[test]
+    fn pointers_must_point_backwards() {
+        // At itself
+        let mut buffer = buffer_of(&[0xC0, 0x00]);
+        assert!(matches!(
+            buffer.read_name(),
+            Err(DnsError::InvalidPointer { .. })
+        ));
+
+        // Forward, even to a perfectly good name
+        let mut buffer = buffer_of(&[0xC0, 0x02, 1, b'a', 0]);
+        assert!(matches!(
+            buffer.read_name(),
+            Err(DnsError::InvalidPointer { .. })
+        ));
+
+        // Back to a label that points at the pointer again
+        let mut buffer = buffer_of(&[1, b'a', 0xC0, 0x04, 0xC0, 0x00]);
+        buffer.pos = 4;
+        assert!(matches!(
+            buffer.read_name(),
+            Err(DnsError::InvalidPointer { .. })
+        ));
+    }
+
+    #[test]
+    fn pointer_chains_are_limited() {
+        // Every pointer leads to the one before it, ending at `a`
+        let mut bytes = vec![1, b'a', 0];
+        for i in 0..10 {
+            let target = if i == 0 { 0 } else { 3 + (i - 1) * 2 };
+            bytes.extend_from_slice(&[0xC0, target as u8]);
+        }
+
+        let mut buffer = buffer_of(&bytes);
+        buffer.pos = 3 + 2;
+        assert_eq!(buffer.read_name().unwrap(), "a");
+
+        buffer.pos = bytes.len() - 2;
+        assert!(matches!(
+            buffer.read_name(),
+            Err(DnsError::JumpLimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn to_bytes_holds_just_the_message() {
+        let mut packet = DNSPacket::query("example.com", QueryType::A);
+        packet.answers.push(a_record("example.com", [192, 0, 2, 1]));
+
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        assert_eq!(packet.to_bytes().unwrap(), buffer.message());
+    }
+
+    #[test]
+    fn to_bytes_goes_past_512_bytes() {
+        let mut packet = txt_response(20);
+
+        let bytes = packet.to_bytes().unwrap();
+
+        assert!(bytes.len() > 512);
+        let response = DNSPacket::from_bytes(&bytes).unwrap();
+        assert!(!response.header.truncated_message);
+        assert_eq!(response.answers.len(), 20);
+    }
+
+    #[test]
+    fn extended_rcodes_round_trip() {
+        for rescode in [
+            ResultCode::NXDOMAIN,
+            ResultCode::BADVERS,
+            ResultCode::BADCOOKIE,
+            ResultCode::UNKNOWN(11),
+            ResultCode::UNKNOWN(3841),
+        ] {
+            let mut packet = txt_response(1);
+            packet.header.rescode = rescode;
+
+            assert_eq!(round_trip(&mut packet).header.rescode, rescode);
+        }
+    }
+
+    #[test]
+    fn flags_words_round_trip() {
+        let mut header = DNSHeader::new();
+        for flags in 0..=u16::MAX {
+            header.set_flags_word(flags);
+            assert_eq!(header.flags_word(), flags);
+        }
+    }
+
+    #[test]
+    fn names_are_limited_to_255_bytes() {
+        let label = "a".repeat(63);
+
+        // 3 labels of 64 bytes with their lengths, 62 and the root's 1
+        let longest = format!("{0}.{0}.{0}.{1}", label, "a".repeat(61));
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname(&longest).unwrap();
+        assert_eq!(buffer.pos, 255);
+
+        let too_long = format!("{0}.{0}.{0}.{1}", label, "a".repeat(62));
+        let mut buffer = BytePacketBuffer::new();
+        assert!(matches!(
+            buffer.write_qname(&too_long),
+            Err(DnsError::NameTooLong)
+        ));
+        // Rejected before anything was written
+        assert_eq!(buffer.pos, 0);
+    }
+
+    #[test]
+    fn labels_are_limited_to_63_bytes() {
+        let mut buffer = BytePacketBuffer::new();
+        assert!(matches!(
+            buffer.write_qname(&format!("{}.com", "a".repeat(64))),
+            Err(DnsError::LabelTooLong)
+        ));
+    }
+
+    fn written(qname: &str) -> Result<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname(qname)?;
+        Ok(buffer.message().to_vec())
+    }
+
+    #[test]
+    fn empty_labels_are_rejected() {
+        for qname in ["a..b", ".a", "a.b..", ".."] {
+            assert!(
+                matches!(written(qname), Err(DnsError::EmptyLabel)),
+                "{}",
+                qname
+            );
+        }
+    }
+
+    #[test]
+    fn a_trailing_dot_stands_for_the_root() {
+        assert_eq!(
+            written("example.com.").unwrap(),
+            written("example.com").unwrap()
+        );
+        assert_eq!(written(".").unwrap(), [0]);
+        assert_eq!(written("").unwrap(), [0]);
+    }
+
+    #[test]
+    fn with_ttl_changes_only_the_ttl() {
+        let record = a_record("example.com", [192, 0, 2, 1]);
+        let clamped = record.with_ttl(60);
+
+        assert_eq!(clamped.ttl(), 60);
+        assert_eq!(clamped.with_ttl(record.ttl()), record);
+    }
 
-    pub fn from_num(num: u16) -> QueryType {
-        match num {
-            1 => QueryType::A,
-            2 => QueryType::NS,
-            5 => QueryType::CNAME,
-            15 => QueryType::MX,
-            28 => QueryType::AAAA,
-            _ => QueryType::UNKNOWN(num),
-        }
+    #[test]
+    fn only_packets_without_the_response_bit_are_queries() {
+        let mut packet = DNSPacket::query("example.com", QueryType::A);
+        assert!(packet.is_query());
+
+        packet.header.response = true;
+        assert!(!packet.is_query());
+        assert!(!round_trip(&mut packet).is_query());
     }
-}
 
-#[derive(Debug)]
-pub struct DNSQuestion {
-    pub name: String,
-    pub qtype: QueryType,
-}
+    #[test]
+    fn naptr_round_trips() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(DNSRecord::NAPTR {
+            domain: "4.3.2.1.5.5.5.0.0.8.1.e164.arpa".into(),
+            class: QueryClass::IN,
+            order: 100,
+            preference: 10,
+            flags: "u".into(),
+            services: "E2U+sip".into(),
+            regexp: "!^.*$!sip:info@example.com!".into(),
+            replacement: "".into(),
+            ttl: 3600,
+        });
+        packet.answers.push(DNSRecord::NAPTR {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            order: 100,
+            preference: 50,
+            flags: "s".into(),
+            services: "SIP+D2U".into(),
+            regexp: "".into(),
+            replacement: "_sip._udp.example.com".into(),
+            ttl: 3600,
+        });
 
-impl DNSQuestion {
-    pub fn new(name: String, qtype: QueryType) -> Self {
-        DNSQuestion { name, qtype }
+        assert_eq!(round_trip(&mut packet).answers, packet.answers);
     }
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        buffer.read_qname(&mut self.name)?;
-        self.qtype = QueryType::from_num(buffer.read_u16()?);
-        // Class
-        let _ = buffer.read_u16()?;
-        Ok(())
+    /// 42 21 54 N 71 06 18 W -24m 1m 10000m 10m, from RFC 1876
+    fn loc_record() -> DNSRecord {
+        DNSRecord::LOC {
+            domain: "cambridge-net.kei.com".into(),
+            class: QueryClass::IN,
+            version: 0,
+            size: 0x12,
+            horiz_pre: 0x16,
+            vert_pre: 0x13,
+            latitude: (1 << 31) + (42 * 3600 + 21 * 60 + 54) * 1000,
+            longitude: (1 << 31) - (71 * 3600 + 6 * 60 + 18) * 1000,
+            altitude: 10_000_000 - 2400,
+            ttl: 3600,
+        }
     }
 
-    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        buffer.write_qname(&self.name)?;
-        let qtype = self.qtype.to_num();
-        buffer.write_u16(qtype)?;
-        buffer.write_u16(1)?;
+    #[test]
+    fn loc_round_trips() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(loc_record());
 
-        Ok(())
+        assert_eq!(round_trip(&mut packet).answers, [loc_record()]);
     }
-}
 
-#[derive(Debug)]
-pub struct DNSHeader {
-    pub id: u16,
-    pub recursion_desired: bool,
-    pub truncated_message: bool,
-    pub authoritative_answer: bool,
-    pub opcode: u8,
-    pub response: bool,
-    pub rescode: ResultCode,
-    pub cheching_disabled: bool,
-    pub authed_data: bool,
-    pub z: bool,
-    pub recursion_available: bool,
-    pub questions: u16,
-    pub answers: u16,
-    pub authoritative_entries: u16,
-    pub resource_entries: u16,
-}
+    #[test]
+    fn loc_records_take_sixteen_bytes() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(loc_record());
+        let mut bytes = packet.to_bytes().unwrap();
 
-impl DNSHeader {
-    pub fn new() -> DNSHeader {
-        DNSHeader {
-            id: 0,
-            recursion_desired: false,
-            truncated_message: false,
-            authoritative_answer: false,
-            opcode: 0,
-            response: false,
-            rescode: ResultCode::NOERROR,
-            cheching_disabled: false,
-            authed_data: false,
-            z: false,
-            recursion_available: false,
-            questions: 0,
-            answers: 0,
-            authoritative_entries: 0,
-            resource_entries: 0,
-        }
+        // RDLENGTH sits right before the 16 bytes of data at the end
+        let rdlength = bytes.len() - 18;
+        assert_eq!(bytes[rdlength..rdlength + 2], [0, 16]);
+
+        bytes[rdlength + 1] = 15;
+        bytes.pop();
+        assert!(matches!(
+            DNSPacket::from_bytes(&bytes),
+            Err(DnsError::Malformed(_))
+        ));
     }
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        self.id = buffer.read_u16()?;
+    #[test]
+    fn dname_redirects_are_synthesized_as_cnames() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(DNSRecord::DNAME {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            target: "example.net".into(),
+            ttl: 300,
+        });
 
-        let flags = buffer.read_u16()?;
-        let a = (flags >> 8) as u8;
-        let b = (flags & 0xFF) as u8;
+        packet.synthesize_cnames("www.example.com");
 
-        self.recursion_desired = (a & (1 << 0)) > 0;
-        self.truncated_message = (a & (1 << 1)) > 0;
-        self.authoritative_answer = (a & (1 << 2)) > 0;
-        self.opcode = (a >> 3) & 0x0F;
-        self.response = (a & (1 << 7)) > 0;
+        assert_eq!(
+            packet.answers[1],
+            DNSRecord::CNAME {
+                domain: "www.example.com".into(),
+                class: QueryClass::IN,
+                host: "www.example.net".into(),
+                ttl: 300,
+            }
+        );
+        assert_eq!(
+            packet
+                .unresolved_cname("www.example.com", QueryType::A)
+                .as_deref(),
+            Some("www.example.net")
+        );
+    }
 
-        self.rescode = ResultCode::from_num(b & 0x0F);
-        self.cheching_disabled = (b & (1 << 4)) > 0;
-        self.authed_data = (b & (1 << 5)) > 0;
-        self.z = (b & (1 << 6)) > 0;
-        self.recursion_available = (b & (1 << 7)) > 0;
+    #[test]
+    fn cnames_from_the_server_are_not_synthesized_again() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(DNSRecord::DNAME {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            target: "example.net".into(),
+            ttl: 300,
+        });
+        packet.answers.push(DNSRecord::CNAME {
+            domain: "www.example.com".into(),
+            class: QueryClass::IN,
+            host: "www.example.net".into(),
+            ttl: 300,
+        });
 
-        self.questions = buffer.read_u16()?;
-        self.answers = buffer.read_u16()?;
-        self.authoritative_entries = buffer.read_u16()?;
-        self.resource_entries = buffer.read_u16()?;
+        packet.synthesize_cnames("www.example.com");
 
-        Ok(())
+        assert_eq!(packet.answers.len(), 2);
     }
-}
 
-impl DNSHeader {
-    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        buffer.write_u16(self.id)?;
+    /// `example.com` at 0, `www` and a pointer to it at 13, a pointer
+    /// to that at 19 and a marker byte at 21
+    fn compressed_names() -> BytePacketBuffer {
+        let mut bytes = b"\x07example\x03com\x00".to_vec();
+        bytes.extend(b"\x03www\xC0\x00");
+        bytes.extend(b"\xC0\x0D");
+        bytes.push(0xAB);
+        buffer_of(&bytes)
+    }
 
-        buffer.write_u8(
-            (self.recursion_desired as u8)
-                | ((self.truncated_message as u8) << 1)
-                | ((self.authoritative_answer as u8) << 2)
-                | (self.opcode << 3)
-                | ((self.response as u8) << 7),
-        )?;
+    fn qname_at(buffer: &mut BytePacketBuffer, pos: usize) -> String {
+        buffer.set_position(pos).unwrap();
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        name
+    }
 
-        buffer.write_u8(
-            (self.rescode as u8)
-                | ((self.cheching_disabled as u8) << 4)
-                | ((self.authed_data as u8) << 5)
-                | ((self.z as u8) << 6)
-                | ((self.recursion_available as u8) << 7),
-        )?;
+    #[test]
+    fn uncompressed_names_end_after_the_root_label() {
+        let mut buffer = compressed_names();
 
-        buffer.write_u16(self.questions)?;
-        buffer.write_u16(self.answers)?;
-        buffer.write_u16(self.authoritative_entries)?;
-        buffer.write_u16(self.resource_entries)?;
+        assert_eq!(qname_at(&mut buffer, 0), "example.com");
+        assert_eq!(buffer.pos, 13);
+    }
 
-        Ok(())
+    #[test]
+    fn labels_then_a_pointer_end_after_the_pointer() {
+        let mut buffer = compressed_names();
+
+        assert_eq!(qname_at(&mut buffer, 13), "www.example.com");
+        assert_eq!(buffer.pos, 19);
     }
-}
 
-pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
-    pub pos: usize,
-}
+    #[test]
+    fn chained_pointers_end_after_the_first_pointer() {
+        let mut buffer = compressed_names();
 
-impl BytePacketBuffer {
-    pub fn new() -> Self {
-        BytePacketBuffer {
-            buf: [0; 512],
-            pos: 0,
-        }
+        assert_eq!(qname_at(&mut buffer, 19), "www.example.com");
+        assert_eq!(buffer.pos, 21);
+        assert_eq!(buffer.read_u8().unwrap(), 0xAB);
     }
 
-    fn read_qname(&mut self, out: &mut String) -> Result<()> {
-        let mut pos = self.pos;
-        let mut jumped = false;
-        // Prevent cycles
-        let max_jumps = 5;
-        let mut curr_jump = 0;
+    fn question(name: &str, qtype: QueryType) -> DNSQuestion {
+        DNSQuestion::new(name.into(), qtype)
+    }
 
-        let mut delimiter = "";
+    #[test]
+    fn questions_match_whatever_the_case() {
+        let sent = question("www.Example.com", QueryType::A);
 
-        loop {
-            if curr_jump > max_jumps {
-                return Err(format!("Exceeded jump limit of {}", max_jumps).into());
-            }
+        assert!(sent.matches(&question("WWW.EXAMPLE.COM", QueryType::A)));
+        assert!(sent.matches(&question("www.example.com.", QueryType::A)));
+        assert!(!sent.matches(&question("www.example.net", QueryType::A)));
+        assert!(!sent.matches(&question("www.example.com", QueryType::AAAA)));
+    }
 
-            // Get length of label
-            let len = self.peek(pos)?;
+    #[test]
+    fn question_types_match_by_number() {
+        let sent = question("example.com", QueryType::NAPTR);
 
-            // Most significant two bits set
-            // Jump to another offset
-            if (len & 0xC0) == 0xC0 {
-                if !jumped {
-                    self.set_position(pos + 2)?;
-                }
+        assert!(sent.matches(&question("example.com", QueryType::UNKNOWN(35))));
+        assert!(question("example.com", QueryType::UNKNOWN(65280))
+            .matches(&question("example.com", QueryType::UNKNOWN(65280))));
+        assert!(!question("example.com", QueryType::UNKNOWN(65280))
+            .matches(&question("example.com", QueryType::UNKNOWN(65281))));
+    }
 
-                let b2 = self.peek(pos + 1)? as u16;
-                let offset = ((len as u16) ^ 0xC0) << 8 | b2;
-                pos = offset as usize;
+    #[test]
+    fn any_is_only_matched_by_any() {
+        let sent = question("example.com", QueryType::ANY);
 
-                jumped = true;
-                curr_jump += 1;
-                continue;
-            } else {
-                // Read label
+        assert!(sent.matches(&question("example.com", QueryType::ANY)));
+        assert!(!sent.matches(&question("example.com", QueryType::A)));
+        assert!(!question("example.com", QueryType::A).matches(&sent));
+    }
 
-                // Skip the length
-                pos += 1;
+    #[test]
+    fn responses_have_to_answer_the_query() {
+        let query = DNSPacket::query("example.com", QueryType::A);
 
-                // End of domain name
-                if len == 0 {
-                    break;
-                }
+        let mut response = DNSPacket::new();
+        response.header.id = query.header.id;
+        response.header.response = true;
+        response.questions = query.questions.clone();
+        assert!(query.validate_response(&response).is_ok());
 
-                out.push_str(delimiter);
-                let str_buf = self.peek_many(pos, len as usize)?;
-                out.push_str(&String::from_utf8_lossy(str_buf).to_lowercase());
-                delimiter = ".";
-                pos += len as usize;
-            }
-        }
+        response.header.id = query.header.id.wrapping_add(1);
+        assert!(matches!(
+            query.validate_response(&response),
+            Err(DnsError::IdMismatch { .. })
+        ));
 
-        if !jumped {
-            self.set_position(pos)?;
-        }
-        Ok(())
+        response.header.id = query.header.id;
+        response.questions = vec![question("example.net", QueryType::A)];
+        assert!(matches!(
+            query.validate_response(&response),
+            Err(DnsError::QuestionMismatch { .. })
+        ));
+
+        response.questions.clear();
+        assert!(matches!(
+            query.validate_response(&response),
+            Err(DnsError::QuestionMismatch { .. })
+        ));
     }
 
-    fn skip(&mut self, amount: usize) -> Result<()> {
-        self.pos += amount;
-        Ok(())
+    #[test]
+    fn unicode_names_go_on_the_wire_as_a_labels() {
+        let mut packet = DNSPacket::query("münchen.de", QueryType::A);
+        let bytes = packet.to_bytes().unwrap();
+
+        // Only ASCII after the header
+        assert!(bytes[12..].is_ascii());
+        assert_eq!(&bytes[12..31], b"\x0exn--mnchen-3ya\x02de\x00");
+        assert_eq!(
+            DNSPacket::from_bytes(&bytes).unwrap().questions[0].name,
+            "xn--mnchen-3ya.de"
+        );
     }
 
-    fn set_position(&mut self, pos: usize) -> Result<()> {
-        self.pos = pos;
+    /// Response with a bit of everything, compressed names included,
+    /// for mangling into malformed input
+    fn varied_response() -> Vec<u8> {
+        let mut packet = referral();
+        packet.questions = DNSPacket::query("example.com", QueryType::MX).questions;
+        packet.answers.push(DNSRecord::MX {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            priority: 10,
+            host: "mail.example.com".into(),
+            ttl: 300,
+        });
+        packet.answers.push(DNSRecord::AAAA {
+            domain: "mail.example.com".into(),
+            class: QueryClass::IN,
+            addr: "2001:db8::1".parse().unwrap(),
+            ttl: 300,
+        });
+        packet
+            .answers
+            .push(txt_record("example.com", "v=spf1 -all"));
+        packet.answers.push(loc_record());
+        packet.resources.push(DNSRecord::opt_with_cookie(1232));
+        packet.to_bytes().unwrap()
+    }
 
-        Ok(())
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = varied_response();
+        assert!(DNSPacket::from_bytes(&bytes).is_ok());
+
+        for len in 0..bytes.len() {
+            assert!(DNSPacket::from_bytes(&bytes[..len]).is_err(), "{}", len);
+        }
     }
 
-    /// Read a single byte and increment the position by one
-    fn read_u8(&mut self) -> Result<u8> {
-        if self.end_of_buf() {
-            return Err("end of buffer".into());
+    #[test]
+    fn mangled_input_never_panics() {
+        let bytes = varied_response();
+
+        for bit in 0..bytes.len() * 8 {
+            let mut mangled = bytes.clone();
+            mangled[bit / 8] ^= 1 << (bit % 8);
+            let _ = DNSPacket::from_bytes(&mangled);
+        }
+
+        for i in 0..bytes.len() {
+            for byte in [0x00, 0x3F, 0xC0, 0xFF] {
+                let mut mangled = bytes.clone();
+                mangled[i] = byte;
+                let _ = DNSPacket::from_bytes(&mangled);
+            }
         }
-        let res = self.buf[self.pos];
-        self.pos += 1;
-        Ok(res)
     }
 
-    /// Read two bytes and increment the position by two
-    fn read_u16(&mut self) -> Result<u16> {
-        let res = ((self.read_u8()? as u16) << 8) | (self.read_u8()? as u16);
-        Ok(res)
+    #[test]
+    fn host_names_have_to_follow_ldh() {
+        assert!(a_record("www.example.com", [192, 0, 2, 1])
+            .check_hostnames()
+            .is_ok());
+        // Unicode names are checked as the A-labels they're sent as
+        assert!(a_record("münchen.de", [192, 0, 2, 1])
+            .check_hostnames()
+            .is_ok());
+        assert!(matches!(
+            a_record("bad_host.example.com", [192, 0, 2, 1]).check_hostnames(),
+            Err(DnsError::InvalidHostname(_))
+        ));
+        assert!(matches!(
+            ns_record("example.com", "ns\u{7}.example.com").check_hostnames(),
+            Err(DnsError::InvalidHostname(_))
+        ));
     }
 
-    /// Read four bytes and increment the position by four
-    fn read_u32(&mut self) -> Result<u32> {
-        let res = ((self.read_u8()? as u32) << 24)
-            | ((self.read_u8()? as u32) << 16)
-            | ((self.read_u8()? as u32) << 8)
-            | (self.read_u8()? as u32);
-        Ok(res)
+    #[test]
+    fn other_names_are_written_as_they_are() {
+        // Service labels and the like aren't host names
+        let txt = txt_record("_dmarc.example.com", "v=DMARC1; p=none");
+        assert!(txt.check_hostnames().is_ok());
+
+        let mut packet = DNSPacket::new();
+        packet.answers.push(txt);
+        assert_eq!(round_trip(&mut packet).answers, packet.answers);
     }
 
-    fn peek(&self, pos: usize) -> Result<u8> {
-        if pos >= self.buf.len() {
-            return Err("end of buffer".into());
-        }
-        Ok(self.buf[pos])
+    #[test]
+    fn bad_host_names_are_only_written_raw() {
+        let mut packet = DNSPacket::new();
+        packet
+            .answers
+            .push(a_record("bad_host.example.com", [192, 0, 2, 1]));
+
+        let mut buffer = BytePacketBuffer::new();
+        assert!(matches!(
+            packet.write(&mut buffer),
+            Err(DnsError::InvalidHostname(_))
+        ));
+        let mut buffer = BytePacketBuffer::new();
+        assert!(matches!(
+            packet.write_truncated(&mut buffer),
+            Err(DnsError::InvalidHostname(_))
+        ));
+
+        // As relayed from another server
+        packet.raw_names = true;
+        assert_eq!(round_trip(&mut packet).answers, packet.answers);
     }
 
-    pub fn peek_many(&self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len >= self.buf.len() {
-            return Err("end of buffer".into());
+    /// Response holding only an A record, with its RDLENGTH replaced
+    fn a_with_rdlength(rdlength: u16) -> Vec<u8> {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(a_record("example.com", [192, 0, 2, 1]));
+        let mut bytes = packet.to_bytes().unwrap();
+
+        // Right before the 4 bytes of address at the end
+        let at = bytes.len() - 6;
+        bytes[at..at + 2].copy_from_slice(&rdlength.to_be_bytes());
+        bytes
+    }
+
+    fn malformed(bytes: &[u8]) -> String {
+        match DNSPacket::from_bytes(bytes) {
+            Err(DnsError::Malformed(message)) => message,
+            other => panic!("expected a malformed packet, got {:?}", other),
         }
-        Ok(&self.buf[start..start + len as usize])
     }
 
-    fn end_of_buf(&self) -> bool {
-        self.pos >= self.buf.len()
+    #[test]
+    fn data_past_the_end_is_rejected() {
+        assert!(DNSPacket::from_bytes(&a_with_rdlength(4)).is_ok());
+
+        assert!(malformed(&a_with_rdlength(5)).contains("but only 4 remain"));
+        assert!(malformed(&a_with_rdlength(u16::MAX)).contains("but only 4 remain"));
     }
-}
 
-impl BytePacketBuffer {
-    fn set_u8_at(&mut self, pos: usize, value: u8) -> Result<()> {
-        if pos > self.buf.len() {
-            return Err("end of buffer".into());
-        }
-        self.buf[pos] = value;
+    #[test]
+    fn data_has_to_take_up_exactly_its_length() {
+        assert!(malformed(&a_with_rdlength(3)).contains("claims 3 bytes of data but takes up 4"));
 
-        Ok(())
+        assert!(malformed(&a_with_rdlength(0)).contains("claims 0 bytes of data but takes up 4"));
     }
 
-    fn set_u16_at(&mut self, pos: usize, value: u16) -> Result<()> {
-        self.set_u8_at(pos, (value >> 8) as u8)?;
-        self.set_u8_at(pos + 1, (value & 0xFF) as u8)?;
+    #[test]
+    fn txt_strings_stay_within_the_data() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(txt_record("example.com", "hello"));
+        let mut bytes = packet.to_bytes().unwrap();
+        // One more record after it, for the string to run into
+        bytes[7] = 2;
+        bytes.extend_from_within(12..);
 
-        Ok(())
+        // Right after the header, the owner name and the fixed fields
+        let string = 12 + 13 + 10;
+        assert_eq!(bytes[string], 5);
+        bytes[string] = 6;
+
+        assert!(matches!(
+            DNSPacket::from_bytes(&bytes),
+            Err(DnsError::Malformed(_))
+        ));
     }
 
-    /// Write a single byte to the buffer and increment
-    /// the position by one.
-    fn write(&mut self, value: u8) -> Result<()> {
-        if self.end_of_buf() {
-            return Err("end of buffer".into());
+    fn caa_record(tag: &str) -> DNSRecord {
+        DNSRecord::CAA {
+            domain: "example.com".into(),
+            class: QueryClass::IN,
+            flags: 0,
+            tag: tag.into(),
+            value: "letsencrypt.org".into(),
+            ttl: 300,
         }
-
-        self.buf[self.pos] = value;
-        self.pos += 1;
-        Ok(())
     }
 
-    fn write_u8(&mut self, value: u8) -> Result<()> {
-        self.write(value)?;
-        Ok(())
+    #[test]
+    fn caa_round_trips() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(caa_record("issue"));
+
+        assert_eq!(round_trip(&mut packet).answers, [caa_record("issue")]);
     }
 
-    fn write_u16(&mut self, value: u16) -> Result<()> {
-        self.write((value >> 8) as u8)?;
-        self.write((value & 0xFF) as u8)?;
-        Ok(())
+    #[test]
+    fn caa_tags_over_255_bytes_are_an_error() {
+        let mut packet = DNSPacket::new();
+        packet.answers.push(caa_record(&"a".repeat(256)));
+
+        assert!(matches!(packet.to_bytes(), Err(DnsError::Malformed(_))));
     }
 
-    fn write_u32(&mut self, value: u32) -> Result<()> {
-        self.write(((value >> 24) & 0xFF) as u8)?;
-        self.write(((value >> 16) & 0xFF) as u8)?;
-        self.write(((value >> 8) & 0xFF) as u8)?;
-        self.write(((value >> 0) & 0xFF) as u8)?;
-        Ok(())
+    /// One record of each variant that has a TTL
+    fn every_record() -> Vec<DNSRecord> {
+        let domain = || "example.com".to_string();
+        let class = QueryClass::IN;
+        let ttl = 300;
+        vec![
+            DNSRecord::UNKNOWN {
+                domain: domain(),
+                class,
+                qtype: 65280,
+                data: vec![1, 2, 3],
+                ttl,
+            },
+            a_record("example.com", [192, 0, 2, 1]),
+            ns_record("example.com", "ns.example.com"),
+            DNSRecord::CNAME {
+                domain: domain(),
+                class,
+                host: "alias.example.net".into(),
+                ttl,
+            },
+            DNSRecord::DNAME {
+                domain: domain(),
+                class,
+                target: "example.net".into(),
+                ttl,
+            },
+            DNSRecord::MX {
+                domain: domain(),
+                class,
+                priority: 10,
+                host: "mail.example.com".into(),
+                ttl,
+            },
+            DNSRecord::AAAA {
+                domain: domain(),
+                class,
+                addr: "2001:db8::1".parse().unwrap(),
+                ttl,
+            },
+            txt_record("example.com", "hello"),
+            DNSRecord::SOA {
+                domain: domain(),
+                class,
+                mname: "ns.example.com".into(),
+                rname: "admin.example.com".into(),
+                serial: 1,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+                ttl,
+            },
+            DNSRecord::PTR {
+                domain: "1.2.0.192.in-addr.arpa".into(),
+                class,
+                host: domain(),
+                ttl,
+            },
+            DNSRecord::SRV {
+                domain: "_sip._udp.example.com".into(),
+                class,
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: "sip.example.com".into(),
+                ttl,
+            },
+            caa_record("issue"),
+            DNSRecord::RRSIG {
+                domain: domain(),
+                class,
+                type_covered: QueryType::A,
+                algorithm: 13,
+                labels: 2,
+                original_ttl: 300,
+                expiration: 1_700_000_000,
+                inception: 1_690_000_000,
+                key_tag: 12345,
+                signer: domain(),
+                signature: vec![0xAB; 64],
+                ttl,
+            },
+            DNSRecord::DNSKEY {
+                domain: domain(),
+                class,
+                flags: 257,
+                protocol: 3,
+                algorithm: 13,
+                public_key: vec![0xCD; 64],
+                ttl,
+            },
+            loc_record(),
+            DNSRecord::NAPTR {
+                domain: domain(),
+                class,
+                order: 100,
+                preference: 10,
+                flags: "u".into(),
+                services: "E2U+sip".into(),
+                regexp: "!^.*$!sip:info@example.com!".into(),
+                replacement: "".into(),
+                ttl,
+            },
+            DNSRecord::SVCB {
+                domain: "_dns.example.com".into(),
+                class,
+                priority: 1,
+                target: "dns.example.com".into(),
+                params: vec![(1, b"\x03dot".to_vec())],
+                ttl,
+            },
+            DNSRecord::HTTPS {
+                domain: domain(),
+                class,
+                priority: 1,
+                target: ".".into(),
+                params: vec![(1, b"\x02h2".to_vec())],
+                ttl,
+            },
+        ]
     }
 
-    fn write_qname(&mut self, qname: &str) -> Result<()> {
-        for label in qname.split(".") {
-            let len = label.len();
-            if len > 0x3f {
-                return Err("label exceeds 63 characters".into());
-            }
+    #[test]
+    fn with_ttl_changes_only_the_ttl_of_every_record() {
+        let records = every_record();
+        let types: HashSet<u16> = records.iter().map(|r| r.query_type().to_num()).collect();
+        assert_eq!(types.len(), records.len());
 
-            self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write_u8(*b)?;
-            }
+        for record in records {
+            let changed = record.with_ttl(60);
+
+            assert_eq!(changed.ttl(), 60, "{:?}", record);
+            assert_ne!(changed, record);
+            assert_eq!(changed.with_ttl(record.ttl()), record);
         }
+    }
 
-        self.write_u8(0)?;
+    #[test]
+    fn opt_records_keep_their_fixed_ttl() {
+        let opt = DNSRecord::opt(1232).with_dnssec_ok(true);
+        let changed = opt.with_ttl(60);
 
-        Ok(())
+        // The TTL field holds the extended rcode, version and flags
+        assert_eq!(changed.ttl(), 0);
+        assert_eq!(changed, opt);
+    }
+
+    #[test]
+    fn names_with_long_labels_leave_nothing_behind() {
+        let mut buffer = BytePacketBuffer::new();
+        let qname = format!("www.{}.example.com", "a".repeat(64));
+
+        assert!(matches!(
+            buffer.write_qname(&qname),
+            Err(DnsError::LabelTooLong)
+        ));
+        assert_eq!(buffer.pos, 0);
+        assert!(buffer.names.is_empty());
+
+        // Nothing to point back to, so written in full
+        buffer.write_qname("example.com").unwrap();
+        assert_eq!(buffer.message(), b"\x07example\x03com\x00");
+    }
+
+    #[test]
+    fn chaos_txt_round_trips() {
+        let mut packet = DNSPacket::query("version.bind", QueryType::TXT);
+        packet.header.response = true;
+        packet.questions[0].class = QueryClass::CH;
+        packet.answers.push(DNSRecord::TXT {
+            domain: "version.bind".into(),
+            class: QueryClass::CH,
+            text: "signpost".into(),
+            ttl: 0,
+        });
+
+        let bytes = packet.to_bytes().unwrap();
+        // QCLASS right after the question's name and type
+        assert_eq!(bytes[12 + 14 + 2..12 + 14 + 4], [0, 3]);
+
+        let parsed = DNSPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.questions, packet.questions);
+        assert_eq!(parsed.questions[0].class, QueryClass::CH);
+        assert_eq!(parsed.answers, packet.answers);
     }
 }
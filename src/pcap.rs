@@ -0,0 +1,169 @@
+//! Capture of the messages the server exchanges with clients, in the
+//! classic pcap format Wireshark and tcpdump read. Each message is
+//! wrapped in just enough Ethernet, IP and UDP to be dissected as DNS.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+
+/// Link type of frames starting with an Ethernet header
+const LINKTYPE_ETHERNET: u32 = 1;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// Appends client queries and the server's responses to a pcap file.
+/// Shared between the threads handling requests, writes are serialized.
+pub struct PcapWriter {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl PcapWriter {
+    /// Creates or truncates the file at `path` and writes the pcap header
+    pub fn create(path: &Path) -> Result<PcapWriter> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        // Little endian, microsecond timestamps, version 2.4
+        out.write_all(&0xA1B2_C3D4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        // Timestamps are UTC and as accurate as they claim to be
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        // Longest frame a DNS message over UDP makes
+        out.write_all(&0xFFFFu32.to_le_bytes())?;
+        out.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        out.flush()?;
+
+        Ok(PcapWriter {
+            out: Mutex::new(out),
+        })
+    }
+
+    /// Records a datagram sent from `src` to `dst` carrying `payload`.
+    /// Mixed address families are both written as IPv6.
+    pub fn record(&self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Result<()> {
+        let frame = frame(src, dst, payload);
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut out = self.out.lock().unwrap();
+        out.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        out.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        out.write_all(&frame)?;
+        // Kept readable while the server runs
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Ethernet frame holding the UDP datagram
+fn frame(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let (src_ip, dst_ip) = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => (IpAddr::V4(src), IpAddr::V4(dst)),
+        (src, dst) => (IpAddr::V6(to_ipv6(src)), IpAddr::V6(to_ipv6(dst))),
+    };
+
+    let udp_len = 8 + payload.len();
+    let mut frame = Vec::with_capacity(14 + 40 + udp_len);
+
+    // Neither end's hardware address is known, zeroes will do
+    frame.extend_from_slice(&[0; 12]);
+    match src_ip {
+        IpAddr::V4(_) => frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes()),
+        IpAddr::V6(_) => frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes()),
+    }
+
+    // Pseudo header, the part of the IP header the UDP checksum covers
+    let mut pseudo = Vec::new();
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let mut header = Vec::with_capacity(20);
+            header.push(0x45);
+            header.push(0);
+            header.extend_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            // Identification, then don't fragment
+            header.extend_from_slice(&[0, 0, 0x40, 0]);
+            header.push(64);
+            header.push(IPPROTO_UDP);
+            header.extend_from_slice(&[0, 0]);
+            header.extend_from_slice(&src_ip.octets());
+            header.extend_from_slice(&dst_ip.octets());
+            let checksum = checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            frame.extend_from_slice(&header);
+
+            pseudo.extend_from_slice(&src_ip.octets());
+            pseudo.extend_from_slice(&dst_ip.octets());
+            pseudo.extend_from_slice(&[0, IPPROTO_UDP]);
+            pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        }
+        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+            frame.extend_from_slice(&[0x60, 0, 0, 0]);
+            frame.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            frame.push(IPPROTO_UDP);
+            frame.push(64);
+            frame.extend_from_slice(&src_ip.octets());
+            frame.extend_from_slice(&dst_ip.octets());
+
+            pseudo.extend_from_slice(&src_ip.octets());
+            pseudo.extend_from_slice(&dst_ip.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, IPPROTO_UDP]);
+        }
+        _ => unreachable!("both addresses share a family"),
+    }
+
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    pseudo.extend_from_slice(&udp);
+    // A computed 0 is sent as all ones, 0 means no checksum
+    let checksum = match checksum(&pseudo) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    frame.extend_from_slice(&udp);
+
+    frame
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Internet checksum, the ones' complement of the ones' complement
+/// sum of the data as 16 bit words
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| match word {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]) as u32,
+            [hi] => (*hi as u32) << 8,
+            _ => 0,
+        })
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::name;
+use crate::packet::{DNSPacket, QueryType, ResultCode};
+
+/// How many sources to track before forgetting the ones that are idle
+const MAX_TRACKED_SOURCES: usize = 10_000;
+
+/// Period responses are counted over for response rate limiting
+const RRL_WINDOW: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per source address. Each source may send `rate`
+/// queries a second on average, with bursts of up to `burst`.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the source's bucket, false once it's empty
+    pub fn allow(&self, source: IpAddr) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+
+        if buckets.len() >= MAX_TRACKED_SOURCES && !buckets.contains_key(&source) {
+            // Sources whose bucket has filled back up are as good as new
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(source).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Tokens in the bucket once the time since its last update is added
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// What to do with a response according to `ResponseRateLimiter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RrlAction {
+    Send,
+    /// Send it empty with the TC bit set, a real client retries over
+    /// TCP while a spoofed victim gets nothing bigger than the query
+    Slip,
+    Drop,
+}
+
+/// Response group a client netblock keeps getting
+#[derive(Hash, PartialEq, Eq)]
+struct ResponseKey {
+    network: IpAddr,
+    qname: String,
+    qtype: QueryType,
+    rescode: ResultCode,
+    empty: bool,
+}
+
+/// Responses counted in the current second and the one before it
+struct Window {
+    start: Instant,
+    current: u32,
+    previous: u32,
+    /// Responses limited so far, which picks the ones to slip
+    limited: u32,
+}
+
+/// Response rate limiting as deployed on authoritative servers. Spoofed
+/// amplification traffic makes the server send the same response to the
+/// same victim over and over, so identical responses to one client
+/// netblock (/24 for IPv4, /56 for IPv6) are limited to `limit` a second.
+/// Past that, every `slip`th response is slipped and the rest dropped.
+pub struct ResponseRateLimiter {
+    limit: u32,
+    slip: u32,
+    windows: Mutex<HashMap<ResponseKey, Window>>,
+}
+
+impl ResponseRateLimiter {
+    pub fn new(limit: u32, slip: u32) -> ResponseRateLimiter {
+        ResponseRateLimiter {
+            limit,
+            slip,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts the response and decides whether it goes out
+    pub fn check(&self, client: IpAddr, response: &DNSPacket) -> RrlAction {
+        let Some(question) = response.questions.first() else {
+            return RrlAction::Send;
+        };
+
+        let key = ResponseKey {
+            network: network(client),
+            qname: name::normalize(&question.name),
+            qtype: question.qtype,
+            rescode: response.header.rescode,
+            empty: response.answers.is_empty(),
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+
+        if windows.len() >= MAX_TRACKED_SOURCES && !windows.contains_key(&key) {
+            // Anything quiet for two windows counts nothing anymore
+            windows.retain(|_, window| now.duration_since(window.start) < 2 * RRL_WINDOW);
+        }
+
+        let window = windows.entry(key).or_insert(Window {
+            start: now,
+            current: 0,
+            previous: 0,
+            limited: 0,
+        });
+
+        let elapsed = now.duration_since(window.start);
+        if elapsed >= 2 * RRL_WINDOW {
+            window.start = now;
+            window.previous = 0;
+            window.current = 0;
+            window.limited = 0;
+        } else if elapsed >= RRL_WINDOW {
+            window.start += RRL_WINDOW;
+            window.previous = window.current;
+            window.current = 0;
+        }
+        window.current += 1;
+
+        // Sliding estimate, the previous window weighs
+        // less the further into the current one we are
+        let into = now.duration_since(window.start).as_secs_f64() / RRL_WINDOW.as_secs_f64();
+        let rate = window.previous as f64 * (1.0 - into) + window.current as f64;
+        if rate <= self.limit as f64 {
+            return RrlAction::Send;
+        }
+
+        // Counted apart from `current`, which can be under the limit
+        // while the previous window still pushes the rate over it
+        window.limited = window.limited.wrapping_add(1);
+        if self.slip > 0 && window.limited.is_multiple_of(self.slip) {
+            RrlAction::Slip
+        } else {
+            RrlAction::Drop
+        }
+    }
+}
+
+/// Netblock a client is counted under, spoofed sources vary in the low bits
+fn network(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) & 0xFFFF_FF00)),
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & !0u128 << 72)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_limited_after_a_burst() {
+        let limiter = RateLimiter::new(1, 3);
+        let source = IpAddr::from([192, 0, 2, 1]);
+
+        for _ in 0..3 {
+            assert!(limiter.allow(source));
+        }
+        assert!(!limiter.allow(source));
+    }
+
+    #[test]
+    fn sources_are_limited_separately() {
+        let limiter = RateLimiter::new(1, 1);
+
+        assert!(limiter.allow(IpAddr::from([192, 0, 2, 1])));
+        assert!(!limiter.allow(IpAddr::from([192, 0, 2, 1])));
+        assert!(limiter.allow(IpAddr::from([192, 0, 2, 2])));
+    }
+
+    #[test]
+    fn buckets_fill_back_up() {
+        let limiter = RateLimiter::new(100, 1);
+        let source = IpAddr::from([192, 0, 2, 1]);
+
+        assert!(limiter.allow(source));
+        assert!(!limiter.allow(source));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.allow(source));
+    }
+
+    fn response(qname: &str) -> DNSPacket {
+        let mut response = DNSPacket::query(qname, QueryType::A);
+        response.header.response = true;
+        response
+    }
+
+    #[test]
+    fn identical_responses_are_slipped_or_dropped() {
+        let limiter = ResponseRateLimiter::new(2, 2);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let response = response("example.com");
+
+        let actions: Vec<RrlAction> = (0..5).map(|_| limiter.check(client, &response)).collect();
+
+        assert_eq!(
+            actions,
+            [
+                RrlAction::Send,
+                RrlAction::Send,
+                RrlAction::Drop,
+                RrlAction::Slip,
+                RrlAction::Drop
+            ]
+        );
+    }
+
+    #[test]
+    fn netblocks_are_counted_together() {
+        let limiter = ResponseRateLimiter::new(1, 0);
+        let response = response("example.com");
+
+        assert_eq!(
+            limiter.check(IpAddr::from([192, 0, 2, 1]), &response),
+            RrlAction::Send
+        );
+        assert_eq!(
+            limiter.check(IpAddr::from([192, 0, 2, 200]), &response),
+            RrlAction::Drop
+        );
+        assert_eq!(
+            limiter.check(IpAddr::from([192, 0, 3, 1]), &response),
+            RrlAction::Send
+        );
+    }
+
+    #[test]
+    fn different_responses_are_counted_apart() {
+        let limiter = ResponseRateLimiter::new(1, 0);
+        let client = IpAddr::from([192, 0, 2, 1]);
+
+        assert_eq!(
+            limiter.check(client, &response("example.com")),
+            RrlAction::Send
+        );
+        assert_eq!(
+            limiter.check(client, &response("EXAMPLE.com")),
+            RrlAction::Drop
+        );
+        assert_eq!(
+            limiter.check(client, &response("example.net")),
+            RrlAction::Send
+        );
+
+        let mut refused = response("example.com");
+        refused.header.rescode = ResultCode::REFUSED;
+        assert_eq!(limiter.check(client, &refused), RrlAction::Send);
+    }
+
+    #[test]
+    fn slipping_keeps_its_rotation_into_the_next_window() {
+        let limiter = ResponseRateLimiter::new(5, 2);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let response = response("example.com");
+
+        for _ in 0..20 {
+            limiter.check(client, &response);
+        }
+        // Early in the next window, the flood before still counts
+        std::thread::sleep(RRL_WINDOW + Duration::from_millis(50));
+
+        let actions: Vec<RrlAction> = (0..4).map(|_| limiter.check(client, &response)).collect();
+
+        assert_eq!(
+            actions,
+            [
+                RrlAction::Slip,
+                RrlAction::Drop,
+                RrlAction::Slip,
+                RrlAction::Drop
+            ]
+        );
+    }
+}
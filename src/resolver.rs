@@ -0,0 +1,756 @@
+//! Resolution of names the server has no authority over, walking down
+//! from the root servers or handing queries to forwarders. The steps
+//! that don't involve I/O are shared with `nonblocking`, which only
+//! differs in how it talks to other servers.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::cache::{Cache, Cached};
+use crate::config::{Config, ResolverMode};
+use crate::error::{DnsError, Result};
+use crate::packet::{DNSPacket, DNSQuestion, QueryType, ResultCode};
+use crate::roots;
+use crate::stats::Stats;
+use crate::stub::{lookup, QueryFlags};
+use crate::upstream::Upstreams;
+
+/// How long to wait on a nameserver before moving on to the next one
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many name servers without glue to resolve at once
+const NS_PARALLELISM: usize = 3;
+
+/// How many lookups for name server addresses may nest inside
+/// each other, a crafted delegation chain could go on forever
+pub(crate) const MAX_RECURSION_DEPTH: usize = 16;
+
+/// How many CNAMEs to follow before settling for what was found
+pub(crate) const MAX_CNAME_CHAIN: usize = 8;
+
+/// Media type of wire format messages sent over HTTPS
+#[cfg(feature = "doh")]
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Upstream queries left for a client request, shared by every
+/// lookup done on its behalf
+#[derive(Clone)]
+pub(crate) struct Budget(Arc<AtomicUsize>);
+
+impl Budget {
+    pub(crate) fn new(queries: usize) -> Budget {
+        Budget(Arc::new(AtomicUsize::new(queries)))
+    }
+
+    /// Takes a query out of the budget, failing once it's spent
+    pub(crate) fn spend(&self) -> Result<()> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .map(|_| ())
+            .map_err(|_| DnsError::QueryLimit)
+    }
+}
+
+/// Everything a lookup needs to share with the rest of the server.
+/// Clones are cheap and refer to the same state, so a context can be
+/// moved into each thread doing lookups.
+#[derive(Clone)]
+pub struct Context {
+    cache: Cache,
+    config: Arc<Config>,
+    /// Forwarders and how they've been doing, in forwarding mode
+    upstreams: Option<Arc<Upstreams>>,
+    stats: Arc<Stats>,
+    budget: Budget,
+    /// DNSSEC bits of the client request, passed on to forwarders
+    flags: QueryFlags,
+}
+
+impl Context {
+    /// Resolves the way `config` says, keeping answers in `cache` and
+    /// counting cache hits and upstream queries in `stats`
+    pub fn new(config: Arc<Config>, cache: Cache, stats: Arc<Stats>) -> Context {
+        let upstreams = match &config.mode {
+            ResolverMode::Forwarding(forwarders) => {
+                Some(Arc::new(Upstreams::new(forwarders.clone())))
+            }
+            ResolverMode::Recursive => None,
+        };
+
+        Context {
+            cache,
+            budget: Budget::new(config.max_upstream_queries),
+            config,
+            upstreams,
+            stats,
+            flags: QueryFlags::default(),
+        }
+    }
+
+    /// Copy that passes the client's DNSSEC bits on to forwarders
+    pub fn with_flags(&self, flags: QueryFlags) -> Context {
+        Context {
+            flags,
+            ..self.clone()
+        }
+    }
+
+    /// Copy for handling a single client request, with a budget of its own
+    pub fn for_request(&self) -> Context {
+        Context {
+            budget: Budget::new(self.config.max_upstream_queries),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the DO bit goes upstream, so the answers come with
+    /// RRSIGs. Only forwarders are asked for them.
+    fn sends_dnssec_ok(&self) -> bool {
+        self.upstreams.is_some() && self.flags.dnssec_ok
+    }
+}
+
+/// Resolves the name, following CNAMEs until reaching a record of
+/// the requested type. The whole chain ends up in the answers.
+///
+/// Answers from servers that aren't authoritative for the name are only
+/// settled for when they don't come with a referral, or the servers it
+/// points to fail. The AA bit of the result tells the two apart, it's
+/// clear for cached answers too.
+pub fn recursive_lookup(qname: &str, qtype: QueryType, ctx: &Context) -> Result<DNSPacket> {
+    nested_lookup(qname, qtype, ctx, 0)
+}
+
+/// `recursive_lookup` for a lookup nested `depth` levels deep
+fn nested_lookup(qname: &str, qtype: QueryType, ctx: &Context, depth: usize) -> Result<DNSPacket> {
+    if depth > MAX_RECURSION_DEPTH {
+        return Err(DnsError::RecursionLimit);
+    }
+
+    let mut response = resolve(qname, qtype, ctx, depth)?;
+
+    for _ in 0..MAX_CNAME_CHAIN {
+        // Whatever a DNAME redirects is followed like any other alias
+        response.synthesize_cnames(qname);
+        let Some(target) = response.unresolved_cname(qname, qtype) else {
+            break;
+        };
+        debug!("Following CNAME from {} to {}", qname, target);
+
+        let result = resolve(&target, qtype, ctx, depth)?;
+        if !extend_chain(&mut response, result) {
+            break;
+        }
+    }
+
+    Ok(response)
+}
+
+/// Adds what resolving the target of a CNAME came up with to the
+/// response, returning whether the chain goes on from there
+pub(crate) fn extend_chain(response: &mut DNSPacket, result: DNSPacket) -> bool {
+    let found = !result.answers.is_empty();
+
+    response.header.rescode = result.header.rescode;
+    // Authoritative only if every link in the chain is
+    response.header.authoritative_answer &= result.header.authoritative_answer;
+    response.answers.extend(result.answers);
+    if !found {
+        // Carry the SOA along for negative answers
+        response.authorities = result.authorities;
+    }
+
+    found
+}
+
+/// Resolves the name starting from the root servers. There's nothing
+/// special about ANY queries, whatever the authoritative server
+/// returns for them is passed along as is.
+fn resolve(qname: &str, qtype: QueryType, ctx: &Context, depth: usize) -> Result<DNSPacket> {
+    let dnssec_ok = ctx.sends_dnssec_ok();
+    if let Some(response) = cached_response(qname, qtype, dnssec_ok, &ctx.cache) {
+        ctx.stats.record_cache_hit();
+
+        let positive = !response.is_negative();
+        if positive && ctx.config.prefetch && ctx.cache.should_prefetch(qname, qtype, dnssec_ok) {
+            prefetch(qname, qtype, ctx);
+        }
+
+        return Ok(response);
+    }
+
+    fetch(qname, qtype, ctx, depth)
+}
+
+/// Response made up of what the cache holds for the question, be it
+/// the answers or that there are none
+pub(crate) fn cached_response(
+    qname: &str,
+    qtype: QueryType,
+    dnssec_ok: bool,
+    cache: &Cache,
+) -> Option<DNSPacket> {
+    let mut response = DNSPacket::new();
+    response
+        .questions
+        .push(DNSQuestion::new(qname.to_string(), qtype));
+
+    match cache.lookup(qname, qtype, dnssec_ok)? {
+        Cached::Answers(answers) => {
+            debug!("Cache hit for {:?} {}", qtype, qname);
+            response.answers = answers;
+        }
+        Cached::Negative(rescode, authorities) => {
+            debug!("Negative cache hit for {:?} {}", qtype, qname);
+            response.header.rescode = rescode;
+            response.authorities = authorities;
+        }
+    }
+
+    Some(response)
+}
+
+/// Refreshes a cache entry in the background, the client that
+/// triggered it is answered from the cache meanwhile
+fn prefetch(qname: &str, qtype: QueryType, ctx: &Context) {
+    debug!("Prefetching {:?} {}", qtype, qname);
+
+    let qname = qname.to_string();
+    // Refreshes the entry the client was served from, CD answers
+    // aren't cached to begin with
+    let flags = QueryFlags {
+        dnssec_ok: ctx.flags.dnssec_ok,
+        ..QueryFlags::default()
+    };
+    let ctx = ctx.for_request().with_flags(flags);
+    thread::spawn(move || {
+        if let Err(e) = fetch(&qname, qtype, &ctx, 0) {
+            debug!("Prefetching {:?} {} failed: {}", qtype, qname, e);
+        }
+    });
+}
+
+/// `resolve` past the cache, asking upstream and caching the answer
+fn fetch(qname: &str, qtype: QueryType, ctx: &Context, depth: usize) -> Result<DNSPacket> {
+    if let Some(url) = &ctx.config.doh_url {
+        ctx.budget.spend()?;
+        ctx.stats.record_hop();
+        let response = lookup_doh(qname, qtype, url).inspect_err(|e| {
+            if e.is_retryable() {
+                ctx.stats.record_upstream_error();
+            }
+        })?;
+        cache_response(qname, qtype, ctx.sends_dnssec_ok(), &response, &ctx.cache);
+        return Ok(response);
+    }
+
+    if let Some(upstreams) = &ctx.upstreams {
+        // The forwarder does the recursion, its response is final
+        let response = lookup_any(qname, qtype, &upstreams.order(), ctx.flags, ctx)?;
+        // Without checking the forwarder may hand out data it knows
+        // to be bogus, which only the client that asked should see
+        if !ctx.flags.checking_disabled {
+            cache_response(qname, qtype, ctx.sends_dnssec_ok(), &response, &ctx.cache);
+        }
+        return Ok(response);
+    }
+
+    let mut servers = match &ctx.config.root_servers {
+        Some(servers) => servers.clone(),
+        None => with_port(roots::rotation()),
+    };
+    // Non-authoritative answer to fall back on should the
+    // referral that came with it lead nowhere
+    let mut fallback = None;
+
+    loop {
+        let response = match lookup_any(qname, qtype, &servers, QueryFlags::default(), ctx) {
+            Ok(response) => response,
+            Err(e) => return fallback.ok_or(e),
+        };
+
+        servers = match next_step(qname, response, &mut fallback) {
+            Step::Done(response) => {
+                cache_response(qname, qtype, ctx.sends_dnssec_ok(), &response, &ctx.cache);
+                return Ok(response);
+            }
+            Step::Servers(servers) => with_port(servers),
+            Step::Resolve(hosts, response) => match resolve_any_ns(hosts, ctx, depth)? {
+                Some(servers) => with_port(servers),
+                // Without an address for any of them there's nowhere
+                // to go, what the server said is all there is
+                None => return Ok(fallback.unwrap_or(response)),
+            },
+        };
+    }
+}
+
+/// Name servers listen on port 53
+fn with_port(servers: Vec<IpAddr>) -> Vec<SocketAddr> {
+    servers
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect()
+}
+
+/// Where a nameserver's response leaves the walk down from the roots
+pub(crate) enum Step {
+    /// Answers or that there are none, the lookup is over
+    Done(DNSPacket),
+    /// Referral to servers whose addresses came along
+    Servers(Vec<IpAddr>),
+    /// Referral to servers whose addresses have to be looked up first,
+    /// with the response to settle for should none of them resolve.
+    /// There may be no servers at all, e.g. for REFUSED.
+    Resolve(Vec<String>, DNSPacket),
+}
+
+/// Decides where to go from a nameserver's response. An answer from a
+/// server that isn't authoritative for the name but points to ones
+/// that are is kept in `fallback` while the referral is followed.
+pub(crate) fn next_step(
+    qname: &str,
+    response: DNSPacket,
+    fallback: &mut Option<DNSPacket>,
+) -> Step {
+    // Answer and no errors -> we're done, unless a server that isn't
+    // authoritative points to ones that are
+    if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
+        if !response.is_referral(qname) {
+            return Step::Done(response);
+        }
+        debug!(
+            "Following referral past non-authoritative answer for {}",
+            qname
+        );
+        *fallback = Some(response.clone());
+    }
+
+    // Name or type doesn't exist
+    if response.is_negative() {
+        return Step::Done(response);
+    }
+
+    // Look somewhere else
+    let resolved: Vec<IpAddr> = response.get_all_resolved_ns(qname).collect();
+    if !resolved.is_empty() {
+        return Step::Servers(resolved);
+    }
+
+    // Resolve NS record to IP
+    let hosts = response
+        .get_all_unresolved_ns(qname)
+        .take(NS_PARALLELISM)
+        .map(|name| name.to_string())
+        .collect();
+    Step::Resolve(hosts, response)
+}
+
+/// Keeps the answers of a final response for the next time the question
+/// comes up, or that there are none when it's negative
+pub(crate) fn cache_response(
+    qname: &str,
+    qtype: QueryType,
+    dnssec_ok: bool,
+    response: &DNSPacket,
+    cache: &Cache,
+) {
+    if response.is_negative() {
+        let rescode = response.header.rescode;
+        cache.store_negative(qname, qtype, dnssec_ok, rescode, &response.authorities);
+    } else if response.header.rescode == ResultCode::NOERROR {
+        cache.store(qname, qtype, dnssec_ok, &response.answers);
+    }
+}
+
+/// Resolves a few name servers at the same time and returns the
+/// addresses of whichever one comes back first, so there's more
+/// than one to try should it not respond. Fails when none of them
+/// resolve because their lookups nested too deep.
+fn resolve_any_ns(hosts: Vec<String>, ctx: &Context, depth: usize) -> Result<Option<Vec<IpAddr>>> {
+    let (tx, rx) = mpsc::channel();
+
+    for host in hosts {
+        let tx = tx.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let addrs = resolve_ns(&host, &ctx, depth);
+            // Nobody is listening anymore once another lookup won
+            let _ = tx.send(addrs);
+        });
+    }
+    drop(tx);
+
+    // Ends once every lookup has reported back
+    let mut result = Ok(None);
+    for addrs in rx {
+        match addrs {
+            Ok(Some(addrs)) => return Ok(Some(addrs)),
+            Ok(None) => {}
+            Err(e) => result = Err(e),
+        }
+    }
+    result
+}
+
+/// Addresses of a name server, falling back to IPv6
+/// for servers that are only reachable over it
+fn resolve_ns(host: &str, ctx: &Context, depth: usize) -> Result<Option<Vec<IpAddr>>> {
+    for qtype in [QueryType::A, QueryType::AAAA] {
+        let addrs: Vec<IpAddr> = match nested_lookup(host, qtype, ctx, depth + 1) {
+            Ok(response) => response.get_all_ip().collect(),
+            // Going deeper for IPv6 won't help
+            Err(DnsError::RecursionLimit) => return Err(DnsError::RecursionLimit),
+            Err(_) => continue,
+        };
+        if !addrs.is_empty() {
+            return Ok(Some(addrs));
+        }
+    }
+    Ok(None)
+}
+
+/// Query the servers in order, moving on to the next one
+/// whenever a server can't be reached or doesn't answer in time
+fn lookup_any(
+    qname: &str,
+    qtype: QueryType,
+    servers: &[SocketAddr],
+    flags: QueryFlags,
+    ctx: &Context,
+) -> Result<DNSPacket> {
+    let mut last_err = None;
+    let mut last_response = None;
+
+    for &ns in servers {
+        debug!("Looking up {:?} {} with ns {}", qtype, qname, ns);
+        ctx.budget.spend()?;
+        ctx.stats.record_hop();
+
+        let result = lookup(
+            qname,
+            qtype,
+            ns,
+            LOOKUP_TIMEOUT,
+            ctx.config.randomize_case,
+            ctx.config.edns_payload_size,
+            flags,
+        );
+
+        // A forwarder that answers at all is up, even with SERVFAIL,
+        // which may well be down to the name
+        if let Some(upstreams) = &ctx.upstreams {
+            match &result {
+                Ok(_) => upstreams.record_success(ns),
+                Err(e) if e.is_retryable() => upstreams.record_failure(ns),
+                Err(_) => {}
+            }
+        }
+
+        match result {
+            // Another server for the same zone may well do better
+            Ok(response) if response.is_server_failure() => {
+                warn!("Nameserver {} answered {}", ns, response.header.rescode);
+                ctx.stats.record_upstream_error();
+                last_response = Some(response);
+            }
+            Err(e) if e.is_retryable() => {
+                warn!("Nameserver {} failed: {}", ns, e);
+                ctx.stats.record_upstream_error();
+                last_err = Some(e);
+            }
+            result => return result,
+        }
+    }
+
+    // Every server failed, pass on what the last one said if anything
+    match last_response {
+        Some(response) => Ok(response),
+        None => Err(last_err.unwrap_or(DnsError::NoNameservers)),
+    }
+}
+
+/// Sends the query to a DNS-over-HTTPS endpoint (RFC 8484),
+/// which does the recursion on our behalf
+#[cfg(feature = "doh")]
+fn lookup_doh(qname: &str, qtype: QueryType, url: &str) -> Result<DNSPacket> {
+    use std::io::Read;
+
+    let mut packet = DNSPacket::query(qname, qtype);
+
+    let response = ureq::post(url)
+        .set("Content-Type", DNS_MESSAGE)
+        .set("Accept", DNS_MESSAGE)
+        .timeout(LOOKUP_TIMEOUT)
+        .send_bytes(&packet.to_bytes()?)
+        .map_err(|e| DnsError::Http(e.to_string()))?;
+
+    if response.status() != 200 {
+        return Err(DnsError::Http(format!(
+            "{} responded with status {}",
+            url,
+            response.status()
+        )));
+    }
+    if response.content_type() != DNS_MESSAGE {
+        return Err(DnsError::Http(format!(
+            "{} responded with {} instead of {}",
+            url,
+            response.content_type(),
+            DNS_MESSAGE
+        )));
+    }
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(0x10000)
+        .read_to_end(&mut body)?;
+
+    let response = DNSPacket::from_bytes(&body)?;
+
+    packet.validate_response(&response)?;
+
+    Ok(response)
+}
+
+#[cfg(not(feature = "doh"))]
+fn lookup_doh(_qname: &str, _qtype: QueryType, _url: &str) -> Result<DNSPacket> {
+    Err(DnsError::Config(
+        "DNS-over-HTTPS requires building with the doh feature".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{DNSRecord, QueryClass};
+    use std::collections::HashSet;
+    use std::net::UdpSocket;
+
+    fn cname(domain: &str, host: &str) -> DNSRecord {
+        DNSRecord::CNAME {
+            domain: domain.into(),
+            class: QueryClass::IN,
+            host: host.into(),
+            ttl: 300,
+        }
+    }
+
+    /// Name server on localhost answering each question with what
+    /// `respond` makes of it, for as long as the tests run
+    fn nameserver<F>(respond: F) -> SocketAddr
+    where
+        F: Fn(&DNSQuestion) -> DNSPacket + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, source)) = socket.recv_from(&mut buf) {
+                let query = DNSPacket::from_bytes(&buf[..len]).unwrap();
+
+                let mut response = respond(&query.questions[0]);
+                response.header.id = query.header.id;
+                response.header.response = true;
+                response.questions = query.questions;
+                let _ = socket.send_to(&response.to_bytes().unwrap(), source);
+            }
+        });
+
+        addr
+    }
+
+    /// Forwarder on localhost giving `answers` for each question
+    fn forwarder(answers: fn(&str) -> Vec<DNSRecord>) -> SocketAddr {
+        nameserver(move |question| {
+            let mut response = DNSPacket::new();
+            response.answers = answers(&question.name);
+            response
+        })
+    }
+
+    /// Walks down from `root` instead of the root servers
+    fn recursing_from(root: SocketAddr) -> Context {
+        let config = Config {
+            root_servers: Some(vec![root]),
+            max_upstream_queries: 1000,
+            ..Config::default()
+        };
+        Context::new(Arc::new(config), Cache::new(), Arc::new(Stats::new()))
+    }
+
+    /// Response delegating the question's name to `hosts`, without glue
+    fn delegation(question: &DNSQuestion, hosts: &[&str]) -> DNSPacket {
+        let mut response = DNSPacket::new();
+        for host in hosts {
+            response.authorities.push(DNSRecord::NS {
+                domain: question.name.clone(),
+                class: QueryClass::IN,
+                host: host.to_string(),
+                ttl: 300,
+            });
+        }
+        response
+    }
+
+    fn context(forwarder: SocketAddr) -> Context {
+        let config = Config {
+            mode: ResolverMode::Forwarding(vec![forwarder]),
+            ..Config::default()
+        };
+        Context::new(Arc::new(config), Cache::new(), Arc::new(Stats::new()))
+    }
+
+    #[test]
+    fn cname_chains_are_followed() {
+        let addr = forwarder(|qname| match qname {
+            "www.example.com" => vec![cname("www.example.com", "cdn.example.net")],
+            "cdn.example.net" => vec![cname("cdn.example.net", "edge.example.org")],
+            "edge.example.org" => vec![DNSRecord::A {
+                domain: "edge.example.org".into(),
+                class: QueryClass::IN,
+                addr: [192, 0, 2, 1].into(),
+                ttl: 300,
+            }],
+            _ => Vec::new(),
+        });
+
+        let response = recursive_lookup("www.example.com", QueryType::A, &context(addr)).unwrap();
+
+        let types: Vec<QueryType> = response.answers.iter().map(|r| r.query_type()).collect();
+        assert_eq!(types, [QueryType::CNAME, QueryType::CNAME, QueryType::A]);
+        assert_eq!(response.get_random_a(), Some([192, 0, 2, 1].into()));
+    }
+
+    #[test]
+    fn cname_loops_end() {
+        let addr = forwarder(|qname| match qname {
+            "a.example.com" => vec![cname("a.example.com", "b.example.com")],
+            _ => vec![cname(qname, "a.example.com")],
+        });
+
+        let response = recursive_lookup("a.example.com", QueryType::A, &context(addr)).unwrap();
+
+        assert!(response.answers.len() <= MAX_CNAME_CHAIN + 1);
+        assert_eq!(response.get_random_a(), None);
+    }
+
+    #[test]
+    fn answers_are_cached() {
+        let addr = forwarder(|qname| vec![cname(qname, "target.example.com")]);
+        let ctx = context(addr);
+
+        recursive_lookup("alias.example.com", QueryType::CNAME, &ctx).unwrap();
+        let response = recursive_lookup("alias.example.com", QueryType::CNAME, &ctx).unwrap();
+
+        assert_eq!(ctx.stats.snapshot().cache_hits, 1);
+        assert_eq!(
+            response.answers,
+            [cname("alias.example.com", "target.example.com")]
+        );
+    }
+
+    #[test]
+    fn servers_that_never_answer_are_skipped() {
+        // Bound so queries aren't refused, but never read from
+        let blackhole = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let answering = forwarder(|qname| vec![cname(qname, "target.example.com")]);
+        let ctx = Context::new(
+            Arc::new(Config::default()),
+            Cache::new(),
+            Arc::new(Stats::new()),
+        );
+
+        let servers = [blackhole.local_addr().unwrap(), answering];
+        let response = lookup_any(
+            "www.example.com",
+            QueryType::CNAME,
+            &servers,
+            QueryFlags::default(),
+            &ctx,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response.answers,
+            [cname("www.example.com", "target.example.com")]
+        );
+        let stats = ctx.stats.snapshot();
+        assert_eq!(stats.hops, 2);
+        assert_eq!(stats.upstream_errors, 1);
+    }
+
+    #[test]
+    fn deep_name_server_indirection_fails() {
+        // Each name server is delegated to another one below it
+        let root = nameserver(|question| delegation(question, &[&format!("ns.{}", question.name)]));
+        let ctx = recursing_from(root);
+
+        let result = recursive_lookup("example.com", QueryType::A, &ctx);
+
+        assert!(matches!(result, Err(DnsError::RecursionLimit)));
+        // One query per level, the A lookup fails before AAAA is tried
+        assert_eq!(ctx.stats.snapshot().hops, MAX_RECURSION_DEPTH as u64 + 1);
+    }
+
+    #[test]
+    fn refusals_are_passed_on() {
+        let root = nameserver(|_| {
+            let mut response = DNSPacket::new();
+            response.header.rescode = ResultCode::REFUSED;
+            response
+        });
+
+        let response =
+            recursive_lookup("example.com", QueryType::A, &recursing_from(root)).unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    }
+
+    #[test]
+    fn empty_answers_without_an_soa_are_passed_on() {
+        let root = nameserver(|_| DNSPacket::new());
+
+        let response =
+            recursive_lookup("example.com", QueryType::A, &recursing_from(root)).unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn referrals_to_unresolvable_servers_are_passed_on() {
+        let root = nameserver(|question| match question.name.as_str() {
+            "www.example.com" => delegation(question, &["ns1.example.net", "ns2.example.net"]),
+            _ => {
+                let mut response = DNSPacket::new();
+                response.header.rescode = ResultCode::NXDOMAIN;
+                response
+            }
+        });
+
+        let response =
+            recursive_lookup("www.example.com", QueryType::A, &recursing_from(root)).unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        let hosts: HashSet<&str> = response
+            .authorities
+            .iter()
+            .filter_map(|record| match record {
+                DNSRecord::NS { host, .. } => Some(host.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hosts, HashSet::from(["ns1.example.net", "ns2.example.net"]));
+    }
+}
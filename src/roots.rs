@@ -0,0 +1,86 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// IPv4 addresses of a.root-servers.net through m.root-servers.net
+pub const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// IPv6 addresses of the same servers, in the same order
+pub const ROOT_SERVERS_V6: [Ipv6Addr; 13] = [
+    Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30),
+    Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),
+    Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),
+    Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd),
+    Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe),
+    Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf),
+    Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d),
+    Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53),
+    Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53),
+    Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30),
+    Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1),
+    Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42),
+    Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35),
+];
+
+static NEXT_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+/// Every root server, starting from a different one on each call
+/// so queries are spread across all of them. Each server's IPv6
+/// address follows its IPv4 one, so single stack networks only
+/// lose a failed send per server.
+pub fn rotation() -> Vec<IpAddr> {
+    let start = NEXT_ROOT.fetch_add(1, Ordering::Relaxed) % ROOT_SERVERS.len();
+
+    ROOT_SERVERS
+        .iter()
+        .zip(ROOT_SERVERS_V6.iter())
+        .cycle()
+        .skip(start)
+        .take(ROOT_SERVERS.len())
+        .flat_map(|(&v4, &v6)| [IpAddr::V4(v4), IpAddr::V6(v6)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_covers_every_root_server() {
+        let servers = rotation();
+
+        assert_eq!(servers.len(), 26);
+        for (v4, v6) in ROOT_SERVERS.iter().zip(ROOT_SERVERS_V6.iter()) {
+            let at = servers
+                .iter()
+                .position(|&ip| ip == IpAddr::V4(*v4))
+                .unwrap();
+            // Same server over IPv6 right after
+            assert_eq!(servers[at + 1], IpAddr::V6(*v6));
+        }
+    }
+
+    #[test]
+    fn rotation_starts_somewhere_else_each_time() {
+        let first = rotation();
+        let second = rotation();
+
+        assert_ne!(first[0], second[0]);
+        // Still in the same order, only shifted
+        let at = first.iter().position(|&ip| ip == second[0]).unwrap();
+        assert_eq!(first[at..], second[..first.len() - at]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::packet::ResultCode;
+
+/// Running counters of what the server has been up to,
+/// safe to update from any thread
+#[derive(Debug, Default)]
+pub struct Stats {
+    queries: AtomicU64,
+    servfails: AtomicU64,
+    nxdomains: AtomicU64,
+    cache_hits: AtomicU64,
+    hops: AtomicU64,
+    upstream_errors: AtomicU64,
+}
+
+/// Copy of the counters at a point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Client queries handled
+    pub queries: u64,
+    /// Responses sent with SERVFAIL
+    pub servfails: u64,
+    /// Responses sent with NXDOMAIN
+    pub nxdomains: u64,
+    /// Lookups answered from the cache
+    pub cache_hits: u64,
+    /// Queries sent to nameservers while recursing
+    pub hops: u64,
+    /// Nameservers that failed to answer
+    pub upstream_errors: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /// Counts a response sent to a client
+    pub fn record_response(&self, rescode: ResultCode) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+
+        match rescode {
+            ResultCode::SERVFAIL => self.servfails.fetch_add(1, Ordering::Relaxed),
+            ResultCode::NXDOMAIN => self.nxdomains.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_hop(&self) {
+        self.hops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_error(&self) {
+        self.upstream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            queries: self.queries.load(Ordering::Relaxed),
+            servfails: self.servfails.load(Ordering::Relaxed),
+            nxdomains: self.nxdomains.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            hops: self.hops.load(Ordering::Relaxed),
+            upstream_errors: self.upstream_errors.load(Ordering::Relaxed),
+        }
+    }
+}
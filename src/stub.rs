@@ -0,0 +1,413 @@
+//! Single queries to a known server. The recursive resolver uses
+//! these for each hop; applications that already have a resolver to
+//! ask can use `resolve` and skip the walk down from the root.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::error::{DnsError, Result};
+use crate::name;
+use crate::packet::{BytePacketBuffer, DNSPacket, DNSRecord, QueryType, ResultCode};
+
+/// How long `resolve` waits on the resolver
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// UDP payload size `resolve` and `Resolver` advertise through EDNS0
+const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// How often a `Resolver`'s receiving thread checks whether the
+/// resolver is still around
+const RECV_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// DNSSEC bits a query carries on behalf of a client, so a forwarder
+/// validates or holds back from validating the way the client asked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryFlags {
+    /// CD, the client does its own validation
+    pub checking_disabled: bool,
+    /// DO, the client wants the RRSIGs along with the answers
+    pub dnssec_ok: bool,
+}
+
+/// Asks `resolver`, a recursive resolver such as 1.1.1.1:53, for the
+/// records of `name` and returns its answers. An empty list means the
+/// name exists without records of that type.
+pub fn resolve(name: &str, qtype: QueryType, resolver: SocketAddr) -> Result<Vec<DNSRecord>> {
+    let name = name::to_ascii(name);
+    let response = lookup(
+        &name,
+        qtype,
+        resolver,
+        RESOLVE_TIMEOUT,
+        false,
+        EDNS_PAYLOAD_SIZE,
+        QueryFlags::default(),
+    )?;
+
+    match response.header.rescode {
+        ResultCode::NOERROR => Ok(response.answers),
+        ResultCode::NXDOMAIN => Err(DnsError::NoAnswer(name.into_owned())),
+        rescode => Err(DnsError::ErrorResponse {
+            server: resolver,
+            rescode,
+        }),
+    }
+}
+
+/// Sends a single query to `server` over UDP, retrying over TCP when
+/// the answer doesn't fit in `payload_size` bytes
+pub fn lookup(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    timeout: Duration,
+    randomize_case: bool,
+    payload_size: u16,
+    flags: QueryFlags,
+) -> Result<DNSPacket> {
+    // Let the OS pick a port so concurrent lookups don't collide,
+    // on the same address family as the server
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let mut packet = if randomize_case {
+        DNSPacket::query(&name::randomize_case(qname), qtype)
+    } else {
+        DNSPacket::query(qname, qtype)
+    };
+    packet.header.checking_disabled = flags.checking_disabled;
+    packet
+        .resources
+        .push(DNSRecord::opt_with_cookie(payload_size).with_dnssec_ok(flags.dnssec_ok));
+
+    socket.send_to(&packet.to_bytes()?, server)?;
+
+    let mut resp_buffer = BytePacketBuffer::with_size(payload_size as usize);
+    let len = socket.recv(&mut resp_buffer.buf)?;
+    resp_buffer.set_len(len);
+
+    let response = DNSPacket::from_buffer(&mut resp_buffer)?;
+
+    packet.validate_response(&response)?;
+
+    // Spoofed responses are unlikely to guess the case we picked
+    if randomize_case && response.questions[0].name != packet.questions[0].name {
+        return Err(DnsError::QuestionMismatch {
+            expected: packet.questions[0].name.clone(),
+            got: response.questions[0].name.clone(),
+        });
+    }
+
+    // Didn't fit in a datagram, retry over TCP
+    if response.header.truncated_message {
+        return lookup_tcp(qname, qtype, server, timeout, flags);
+    }
+
+    Ok(response)
+}
+
+pub fn lookup_tcp(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    timeout: Duration,
+    flags: QueryFlags,
+) -> Result<DNSPacket> {
+    // A server that never accepts shouldn't hold up the lookup any
+    // longer than one that never answers
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut packet = DNSPacket::query(qname, qtype);
+    packet.header.checking_disabled = flags.checking_disabled;
+    if flags.dnssec_ok {
+        // The DO bit needs an OPT record to travel in
+        packet
+            .resources
+            .push(DNSRecord::opt(u16::MAX).with_dnssec_ok(true));
+    }
+
+    packet.write_tcp(&mut stream)?;
+
+    let response = DNSPacket::read_tcp(&mut stream)?;
+
+    packet.validate_response(&response)?;
+
+    Ok(response)
+}
+
+/// Transfers `zone` from `server`, which has to allow it for the
+/// address asking, and returns the zone's records starting with its
+/// SOA. The SOA the server repeats to close the transfer is left out.
+pub fn zone_transfer(zone: &str, server: SocketAddr) -> Result<Vec<DNSRecord>> {
+    let zone = name::to_ascii(zone);
+    let mut stream = TcpStream::connect_timeout(&server, RESOLVE_TIMEOUT)?;
+    stream.set_read_timeout(Some(RESOLVE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESOLVE_TIMEOUT))?;
+
+    let mut packet = DNSPacket::query(&zone, QueryType::AXFR);
+    packet.write_tcp(&mut stream)?;
+
+    let mut records: Vec<DNSRecord> = Vec::new();
+    loop {
+        // The zone comes in as many messages as the server cares to
+        // split it into, only the first has to repeat the question
+        let response = DNSPacket::read_tcp(&mut stream)?;
+        if records.is_empty() || !response.questions.is_empty() {
+            packet.validate_response(&response)?;
+        } else if response.header.id != packet.header.id {
+            return Err(DnsError::IdMismatch {
+                expected: packet.header.id,
+                got: response.header.id,
+            });
+        }
+
+        if response.header.rescode != ResultCode::NOERROR {
+            return Err(DnsError::ErrorResponse {
+                server,
+                rescode: response.header.rescode,
+            });
+        }
+
+        for record in response.answers {
+            let is_soa = record.query_type() == QueryType::SOA;
+            if records.is_empty() && !is_soa {
+                return Err(DnsError::Malformed(format!(
+                    "Transfer of {} doesn't start with its SOA",
+                    zone
+                )));
+            }
+            // Another SOA marks the end, anything after it is ignored
+            if is_soa && !records.is_empty() {
+                return Ok(records);
+            }
+            records.push(record);
+        }
+    }
+}
+
+/// Query waiting for its response, by id
+struct Pending {
+    server: SocketAddr,
+    tx: mpsc::Sender<DNSPacket>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    pending: Mutex<HashMap<u16, Pending>>,
+}
+
+/// Sends queries from a single UDP socket, however many are
+/// outstanding at once. Responses are told apart by their id, which
+/// is unique among the queries in flight, and handed to the caller
+/// waiting on it by a thread that does all the receiving.
+///
+/// `lookup` by contrast binds a socket for each query.
+pub struct Resolver {
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    /// Binds the socket to `local`, e.g. `0.0.0.0:0` to query IPv4
+    /// servers from a port the OS picks. Lookups give up on a server
+    /// after `timeout`.
+    pub fn bind(local: SocketAddr, timeout: Duration) -> Result<Resolver> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(RECV_POLL_INTERVAL))?;
+        let shared = Arc::new(Shared {
+            socket,
+            pending: Mutex::new(HashMap::new()),
+        });
+
+        // Only holds on to the socket while receiving, so it's closed
+        // and the thread ends once the resolver is dropped
+        let weak = Arc::downgrade(&shared);
+        thread::spawn(move || receive(weak));
+
+        Ok(Resolver { shared, timeout })
+    }
+
+    /// Sends a single query to `server` and waits for its response,
+    /// retrying over TCP should it not fit in a datagram
+    pub fn lookup(&self, qname: &str, qtype: QueryType, server: SocketAddr) -> Result<DNSPacket> {
+        let (tx, rx) = mpsc::channel();
+
+        let mut packet = DNSPacket::query(qname, qtype);
+        packet
+            .resources
+            .push(DNSRecord::opt_with_cookie(EDNS_PAYLOAD_SIZE));
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+            // Ids have to be unique among the queries in flight
+            while pending.contains_key(&packet.header.id) {
+                packet.header.id = DNSPacket::query(qname, qtype).header.id;
+            }
+            pending.insert(packet.header.id, Pending { server, tx });
+        }
+        let id = packet.header.id;
+
+        let result = packet
+            .to_bytes()
+            .and_then(|data| Ok(self.shared.socket.send_to(&data, server)?))
+            .and_then(|_| rx.recv_timeout(self.timeout).map_err(|_| DnsError::Timeout));
+        // Still there unless the response came in
+        self.shared.pending.lock().unwrap().remove(&id);
+        let response = result?;
+
+        packet.validate_response(&response)?;
+
+        // Didn't fit in a datagram, retry over TCP
+        if response.header.truncated_message {
+            return lookup_tcp(qname, qtype, server, self.timeout, QueryFlags::default());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Hands each response to the query waiting on it, until the
+/// resolver goes away
+fn receive(shared: Weak<Shared>) {
+    while let Some(shared) = shared.upgrade() {
+        let mut buffer = BytePacketBuffer::with_size(EDNS_PAYLOAD_SIZE as usize);
+        let source = match shared.socket.recv_from(&mut buffer.buf) {
+            Ok((len, source)) => {
+                buffer.set_len(len);
+                source
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to receive response: {}", e);
+                continue;
+            }
+        };
+
+        let response = match DNSPacket::from_buffer(&mut buffer) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Dropping unreadable response from {}: {}", source, e);
+                continue;
+            }
+        };
+
+        let mut pending = shared.pending.lock().unwrap();
+        // Only the server the query went to gets to answer it
+        match pending.get(&response.header.id) {
+            Some(query) if query.server == source => {
+                if let Some(query) = pending.remove(&response.header.id) {
+                    let _ = query.tx.send(response);
+                }
+            }
+            _ => debug!(
+                "Dropping response {} from {} nobody waits on",
+                response.header.id, source
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::QueryClass;
+
+    /// Server on localhost that waits for `count` queries, then answers
+    /// them in the reverse order with an A record each
+    fn reversing_server(count: usize) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut queries = Vec::new();
+            while queries.len() < count {
+                let mut buf = [0; 512];
+                let (len, source) = socket.recv_from(&mut buf).unwrap();
+                queries.push((DNSPacket::from_bytes(&buf[..len]).unwrap(), source));
+            }
+
+            for (query, source) in queries.into_iter().rev() {
+                let mut response = DNSPacket::new();
+                response.header.id = query.header.id;
+                response.header.response = true;
+                response.answers.push(DNSRecord::A {
+                    domain: query.questions[0].name.clone(),
+                    class: QueryClass::IN,
+                    addr: [192, 0, 2, 1].into(),
+                    ttl: 300,
+                });
+                response.questions = query.questions;
+                socket
+                    .send_to(&response.to_bytes().unwrap(), source)
+                    .unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn overlapping_lookups_share_a_socket() {
+        let server = reversing_server(2);
+        let resolver = Resolver::bind("127.0.0.1:0".parse().unwrap(), RESOLVE_TIMEOUT).unwrap();
+
+        let names = ["one.example.com", "two.example.com"];
+        thread::scope(|scope| {
+            let lookups: Vec<_> = names
+                .iter()
+                .map(|&name| scope.spawn(|| resolver.lookup(name, QueryType::A, server)))
+                .collect();
+
+            for (lookup, name) in lookups.into_iter().zip(names) {
+                let response = lookup.join().unwrap().unwrap();
+                assert_eq!(response.answers[0].domain(), name);
+            }
+        });
+    }
+
+    #[test]
+    fn responses_from_other_servers_are_ignored() {
+        // Queried but never answers, the response comes from elsewhere
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = silent.local_addr().unwrap();
+        let resolver =
+            Resolver::bind("127.0.0.1:0".parse().unwrap(), Duration::from_secs(1)).unwrap();
+
+        thread::scope(|scope| {
+            let lookup = scope.spawn(|| resolver.lookup("example.com", QueryType::A, server));
+
+            let mut buf = [0; 512];
+            let (len, source) = silent.recv_from(&mut buf).unwrap();
+            let query = DNSPacket::from_bytes(&buf[..len]).unwrap();
+
+            let mut response = DNSPacket::new();
+            response.header.id = query.header.id;
+            response.header.response = true;
+            response.questions = query.questions;
+            let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+            spoofer
+                .send_to(&response.to_bytes().unwrap(), source)
+                .unwrap();
+
+            assert!(matches!(lookup.join().unwrap(), Err(DnsError::Timeout)));
+        });
+    }
+}
@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+/// Failures in a row after which an upstream is considered down
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long a down upstream is skipped before it gets another chance
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+/// Resolvers to forward to, in order of preference. An upstream that
+/// keeps failing is passed over for a while so queries don't wait on
+/// its timeout first.
+pub struct Upstreams {
+    addrs: Vec<SocketAddr>,
+    health: Mutex<Vec<Health>>,
+}
+
+impl Upstreams {
+    pub fn new(addrs: Vec<SocketAddr>) -> Upstreams {
+        let health = addrs
+            .iter()
+            .map(|_| Health {
+                failures: 0,
+                down_until: None,
+            })
+            .collect();
+
+        Upstreams {
+            addrs,
+            health: Mutex::new(health),
+        }
+    }
+
+    /// Upstreams to try in turn, the healthy ones in configured order
+    /// followed by the ones that are down, in case every one of them is
+    pub fn order(&self) -> Vec<SocketAddr> {
+        let health = self.health.lock().unwrap();
+        let now = Instant::now();
+
+        let (up, down): (Vec<_>, Vec<_>) = self
+            .addrs
+            .iter()
+            .zip(health.iter())
+            .partition(|(_, health)| health.down_until.is_none_or(|until| until <= now));
+
+        up.into_iter().chain(down).map(|(&addr, _)| addr).collect()
+    }
+
+    /// Marks the upstream healthy again
+    pub fn record_success(&self, addr: SocketAddr) {
+        if let Some(health) = self.find(&mut self.health.lock().unwrap(), addr) {
+            health.failures = 0;
+            health.down_until = None;
+        }
+    }
+
+    /// Counts a failure, taking the upstream down for the cooldown once
+    /// there have been too many in a row. Each failure after that, such
+    /// as the one retry once the cooldown is over, starts it anew.
+    pub fn record_failure(&self, addr: SocketAddr) {
+        if let Some(health) = self.find(&mut self.health.lock().unwrap(), addr) {
+            health.failures += 1;
+            if health.failures >= FAILURE_THRESHOLD {
+                if health.failures == FAILURE_THRESHOLD {
+                    warn!("Upstream {} is down, skipping it for {:?}", addr, COOLDOWN);
+                }
+                health.down_until = Some(Instant::now() + COOLDOWN);
+            }
+        }
+    }
+
+    fn find<'a>(&self, health: &'a mut [Health], addr: SocketAddr) -> Option<&'a mut Health> {
+        let index = self.addrs.iter().position(|&a| a == addr)?;
+        health.get_mut(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstreams() -> (Upstreams, SocketAddr, SocketAddr) {
+        let primary = SocketAddr::from(([192, 0, 2, 1], 53));
+        let secondary = SocketAddr::from(([192, 0, 2, 2], 53));
+        (Upstreams::new(vec![primary, secondary]), primary, secondary)
+    }
+
+    #[test]
+    fn upstreams_are_tried_in_configured_order() {
+        let (upstreams, primary, secondary) = upstreams();
+
+        assert_eq!(upstreams.order(), [primary, secondary]);
+    }
+
+    #[test]
+    fn failing_upstreams_go_last() {
+        let (upstreams, primary, secondary) = upstreams();
+
+        for _ in 1..FAILURE_THRESHOLD {
+            upstreams.record_failure(primary);
+        }
+        assert_eq!(upstreams.order(), [primary, secondary]);
+
+        upstreams.record_failure(primary);
+        assert_eq!(upstreams.order(), [secondary, primary]);
+    }
+
+    #[test]
+    fn a_success_restores_the_order() {
+        let (upstreams, primary, secondary) = upstreams();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            upstreams.record_failure(primary);
+        }
+        upstreams.record_success(primary);
+
+        assert_eq!(upstreams.order(), [primary, secondary]);
+    }
+
+    #[test]
+    fn failures_in_between_successes_are_forgiven() {
+        let (upstreams, primary, secondary) = upstreams();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            upstreams.record_failure(primary);
+            upstreams.record_success(primary);
+        }
+
+        assert_eq!(upstreams.order(), [primary, secondary]);
+    }
+}
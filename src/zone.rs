@@ -0,0 +1,472 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{DnsError, Result};
+use crate::name;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, QueryClass, QueryType, ResultCode};
+
+/// How many CNAMEs within the zone to follow for one question
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Records a server is authoritative for, loaded from a simple text format
+/// with one record per line:
+///
+/// ```text
+/// $ORIGIN lab.example
+/// @      3600 SOA   ns admin 1 7200 900 1209600 300
+/// @      3600 NS    ns
+/// ns     3600 A     10.0.0.1
+/// www    300  CNAME ns
+/// ```
+///
+/// `@` stands for the origin and names without a trailing dot are
+/// relative to it. Lines starting with `;` are comments. A `*` name
+/// answers for any name below its parent that isn't in the zone.
+pub struct Zone {
+    pub origin: String,
+    records: HashMap<String, Vec<DNSRecord>>,
+    /// Every name that exists in the zone, which includes the empty
+    /// non-terminals between the origin and names further down
+    names: HashSet<String>,
+}
+
+impl Zone {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Zone> {
+        let text = fs::read_to_string(path)?;
+        Zone::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Zone> {
+        let mut origin: Option<String> = None;
+        let mut records: HashMap<String, Vec<DNSRecord>> = HashMap::new();
+
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields[0] == "$ORIGIN" {
+                let name = fields
+                    .get(1)
+                    .ok_or_else(|| DnsError::Zone("$ORIGIN is missing a name".into()))?;
+                origin = Some(name.trim_end_matches('.').to_lowercase());
+                continue;
+            }
+
+            let origin = origin
+                .as_deref()
+                .ok_or_else(|| DnsError::Zone("$ORIGIN must come before any records".into()))?;
+            let record = parse_record(origin, &fields).map_err(|e| match e {
+                DnsError::Zone(msg) => DnsError::Zone(format!("line {}: {}", num + 1, msg)),
+                e => e,
+            })?;
+
+            records
+                .entry(record.domain().to_string())
+                .or_default()
+                .push(record);
+        }
+
+        let origin = origin.ok_or_else(|| DnsError::Zone("no $ORIGIN".into()))?;
+
+        let mut names = HashSet::new();
+        for owner in records.keys() {
+            let mut name = owner.as_str();
+            // Ancestors up to the origin exist, records of their own or not
+            while names.insert(name.to_string()) && name != origin {
+                match name.split_once('.') {
+                    Some((_, parent)) if name::is_subdomain(parent, &origin) => name = parent,
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(Zone {
+            origin,
+            records,
+            names,
+        })
+    }
+
+    /// Whether the name falls within this zone
+    pub fn is_authoritative(&self, qname: &str) -> bool {
+        name::is_subdomain(qname, &self.origin)
+    }
+
+    /// Answers the question from the zone data, or `None` when
+    /// the name is outside the zone and should be resolved elsewhere.
+    /// CNAMEs to other names in the zone are followed, the answers hold
+    /// the whole chain and the rescode is that of its last name.
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DNSPacket> {
+        if !self.is_authoritative(qname) {
+            return None;
+        }
+
+        let mut response = DNSPacket::new();
+        response.header.authoritative_answer = true;
+        response
+            .questions
+            .push(DNSQuestion::new(qname.to_string(), qtype));
+
+        let mut name = qname.to_string();
+        // Whether the last name in the chain has nothing to answer with
+        let mut negative = true;
+        for _ in 0..MAX_CNAME_CHAIN {
+            let Some(answers) = self.answers_at(&name, qtype) else {
+                response.header.rescode = ResultCode::NXDOMAIN;
+                break;
+            };
+            negative = answers.is_empty();
+
+            // Asking for the CNAME itself or for everything ends here
+            let target = answers.iter().find_map(|record| match record {
+                DNSRecord::CNAME { host, .. }
+                    if qtype != QueryType::CNAME && qtype != QueryType::ANY =>
+                {
+                    Some(host.clone())
+                }
+                _ => None,
+            });
+            response.answers.extend(answers);
+
+            match target {
+                Some(target) if self.is_authoritative(&target) => name = target,
+                _ => break,
+            }
+        }
+
+        // Negative answers carry the SOA so clients know how long to
+        // remember them, CNAMEs leading up to them or not
+        if negative {
+            response.authorities = self.soa().cloned().into_iter().collect();
+        }
+
+        Some(response)
+    }
+
+    /// Records of the type at the name, or the CNAME standing in for
+    /// them. `None` when there's no such name in the zone, names with
+    /// nothing but other names below them exist without any records.
+    fn answers_at(&self, qname: &str, qtype: QueryType) -> Option<Vec<DNSRecord>> {
+        let key = name::find_match(qname, |name| self.names.contains(name))?;
+        let wildcard = key != name::normalize(qname);
+
+        let answers = self
+            .records
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter(|record| {
+                qtype == QueryType::ANY
+                    || record.query_type() == qtype
+                    || matches!(record, DNSRecord::CNAME { .. })
+            })
+            .map(|record| {
+                // Records matched through a wildcard take on the name
+                // as it was asked for
+                let mut record = record.clone();
+                if wildcard {
+                    record.set_domain(qname.trim_end_matches('.'));
+                }
+                record
+            })
+            .collect();
+
+        Some(answers)
+    }
+
+    fn soa(&self) -> Option<&DNSRecord> {
+        self.records
+            .get(&self.origin)?
+            .iter()
+            .find(|record| matches!(record, DNSRecord::SOA { .. }))
+    }
+}
+
+fn parse<T>(value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| DnsError::Zone(format!("{}: {}", value, e)))
+}
+
+/// Expands `@` and relative names against the origin
+fn absolute_name(origin: &str, name: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_lowercase()
+    } else {
+        format!("{}.{}", name.to_lowercase(), origin)
+    }
+}
+
+fn parse_record(origin: &str, fields: &[&str]) -> Result<DNSRecord> {
+    if fields.len() < 4 {
+        return Err(DnsError::Zone("expected name, TTL, type and data".into()));
+    }
+
+    let domain = absolute_name(origin, fields[0]);
+    // Zones only hold internet records
+    let class = QueryClass::IN;
+    let ttl: u32 = parse(fields[1])?;
+    let rdata = &fields[3..];
+
+    let field = |i: usize| -> Result<&str> {
+        rdata
+            .get(i)
+            .copied()
+            .ok_or_else(|| DnsError::Zone(format!("{} is missing data", fields[2])))
+    };
+
+    // Same mnemonics as `signpost query` takes
+    let qtype: QueryType = fields[2]
+        .parse()
+        .map_err(|_| DnsError::Zone(format!("unknown record type {}", fields[2])))?;
+    let record = match qtype {
+        QueryType::A => DNSRecord::A {
+            domain,
+            class,
+            addr: parse(field(0)?)?,
+            ttl,
+        },
+        QueryType::AAAA => DNSRecord::AAAA {
+            domain,
+            class,
+            addr: parse(field(0)?)?,
+            ttl,
+        },
+        QueryType::NS => DNSRecord::NS {
+            domain,
+            class,
+            host: absolute_name(origin, field(0)?),
+            ttl,
+        },
+        QueryType::CNAME => DNSRecord::CNAME {
+            domain,
+            class,
+            host: absolute_name(origin, field(0)?),
+            ttl,
+        },
+        QueryType::PTR => DNSRecord::PTR {
+            domain,
+            class,
+            host: absolute_name(origin, field(0)?),
+            ttl,
+        },
+        QueryType::MX => DNSRecord::MX {
+            domain,
+            class,
+            priority: parse(field(0)?)?,
+            host: absolute_name(origin, field(1)?),
+            ttl,
+        },
+        QueryType::TXT => DNSRecord::TXT {
+            domain,
+            class,
+            text: rdata.join(" ").trim_matches('"').to_string(),
+            ttl,
+        },
+        QueryType::SOA => DNSRecord::SOA {
+            domain,
+            class,
+            mname: absolute_name(origin, field(0)?),
+            rname: absolute_name(origin, field(1)?),
+            serial: parse(field(2)?)?,
+            refresh: parse(field(3)?)?,
+            retry: parse(field(4)?)?,
+            expire: parse(field(5)?)?,
+            minimum: parse(field(6)?)?,
+            ttl,
+        },
+        QueryType::SRV => DNSRecord::SRV {
+            domain,
+            class,
+            priority: parse(field(0)?)?,
+            weight: parse(field(1)?)?,
+            port: parse(field(2)?)?,
+            target: absolute_name(origin, field(3)?),
+            ttl,
+        },
+        QueryType::CAA => {
+            // Value may contain spaces, make sure there's at least one word
+            field(2)?;
+            DNSRecord::CAA {
+                domain,
+                class,
+                flags: parse(field(0)?)?,
+                tag: field(1)?.to_lowercase(),
+                value: rdata[2..].join(" ").trim_matches('"').to_string(),
+                ttl,
+            }
+        }
+        other => return Err(DnsError::Zone(format!("unsupported record type {}", other))),
+    };
+
+    // Caught here, where the line can be reported, rather than on every answer
+    record.check_hostnames().map_err(|e| match e {
+        DnsError::InvalidHostname(name) => {
+            DnsError::Zone(format!("{} is not a valid host name", name))
+        }
+        e => e,
+    })?;
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "
+        $ORIGIN lab.example
+        ; comments and blank lines are skipped
+
+        @      3600 SOA   ns admin 1 7200 900 1209600 300
+        @      3600 NS    ns
+        ns     3600 A     10.0.0.1
+        www    300  CNAME ns
+        ftp    300  CNAME www
+        out    300  CNAME example.com.
+        *.dev  300  A     10.0.0.2
+    ";
+
+    fn zone() -> Zone {
+        Zone::parse(ZONE).unwrap()
+    }
+
+    #[test]
+    fn answers_from_the_zone() {
+        let response = zone().lookup("ns.lab.example", QueryType::A).unwrap();
+
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.get_random_a(), Some("10.0.0.1".parse().unwrap()));
+        assert!(response.authorities.is_empty());
+    }
+
+    #[test]
+    fn names_outside_the_zone_are_left_alone() {
+        assert!(zone().lookup("example.com", QueryType::A).is_none());
+    }
+
+    #[test]
+    fn missing_names_and_types_come_with_the_soa() {
+        let zone = zone();
+
+        let response = zone.lookup("nope.lab.example", QueryType::A).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+        assert!(matches!(response.authorities[..], [DNSRecord::SOA { .. }]));
+
+        let response = zone.lookup("ns.lab.example", QueryType::MX).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert!(matches!(response.authorities[..], [DNSRecord::SOA { .. }]));
+    }
+
+    #[test]
+    fn cnames_within_the_zone_are_followed() {
+        let response = zone().lookup("ftp.lab.example", QueryType::A).unwrap();
+
+        let types: Vec<QueryType> = response.answers.iter().map(|r| r.query_type()).collect();
+        assert_eq!(types, [QueryType::CNAME, QueryType::CNAME, QueryType::A]);
+        assert_eq!(response.get_random_a(), Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn cnames_out_of_the_zone_are_left_to_the_resolver() {
+        let response = zone().lookup("out.lab.example", QueryType::A).unwrap();
+
+        assert!(matches!(
+            &response.answers[..],
+            [DNSRecord::CNAME { host, .. }] if host == "example.com"
+        ));
+    }
+
+    #[test]
+    fn wildcards_answer_with_the_name_asked_for() {
+        let response = zone().lookup("Host.DEV.lab.example", QueryType::A).unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers[0].domain(), "Host.DEV.lab.example");
+        assert_eq!(response.get_random_a(), Some("10.0.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn record_types_are_case_insensitive() {
+        let zone = Zone::parse("$ORIGIN lab.example\nhost 60 aaaa ::1").unwrap();
+
+        let response = zone.lookup("host.lab.example", QueryType::AAAA).unwrap();
+        assert_eq!(response.get_random_aaaa(), Some("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        let error = |text: &str| match Zone::parse(text) {
+            Err(DnsError::Zone(msg)) => msg,
+            other => panic!("expected a zone error, got {:?}", other.err()),
+        };
+
+        assert_eq!(
+            error("host 60 A 10.0.0.1"),
+            "$ORIGIN must come before any records"
+        );
+        assert_eq!(
+            error("$ORIGIN lab.example\nhost 60 BOGUS x"),
+            "line 2: unknown record type BOGUS"
+        );
+        assert_eq!(
+            error("$ORIGIN lab.example\nhost 60 MX 10"),
+            "line 2: MX is missing data"
+        );
+        assert_eq!(
+            error("$ORIGIN lab.example\nbad_host 60 A 10.0.0.1"),
+            "line 2: bad_host.lab.example is not a valid host name"
+        );
+        assert_eq!(
+            error("$ORIGIN lab.example\nwww 60 CNAME -web"),
+            "line 2: -web.lab.example is not a valid host name"
+        );
+    }
+
+    #[test]
+    fn empty_non_terminals_exist() {
+        let response = zone().lookup("dev.lab.example", QueryType::A).unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert!(matches!(response.authorities[..], [DNSRecord::SOA { .. }]));
+    }
+
+    #[test]
+    fn wildcards_stop_at_empty_non_terminals() {
+        let zone = Zone::parse(
+            "
+            $ORIGIN lab.example
+            @      3600 SOA ns admin 1 7200 900 1209600 300
+            *      300  A   10.0.0.1
+            a.dev  300  A   10.0.0.2
+            ",
+        )
+        .unwrap();
+
+        let response = zone.lookup("www.lab.example", QueryType::A).unwrap();
+        assert_eq!(response.get_random_a(), Some("10.0.0.1".parse().unwrap()));
+
+        let response = zone.lookup("dev.lab.example", QueryType::A).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+
+        let response = zone.lookup("b.dev.lab.example", QueryType::A).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    }
+}